@@ -0,0 +1,72 @@
+use std::{fs, path::Path};
+
+use failure::{bail, ensure};
+use xmas_elf::{
+    header,
+    sections::{SectionData, ShType},
+    symbol_table::Entry,
+    ElfFile,
+};
+
+/// The variable, defined by `microamp`, that holds the ABI hash of an image
+const SYMBOL: &str = "__microamp_abi";
+
+/// Writes `hash` into the `__microamp_abi` variable of the image at `path`
+///
+/// Images that don't use the ABI hash API don't contain the variable and are left untouched, as
+/// are images that already hold `hash`
+pub(crate) fn embed(path: &Path, hash: u32) -> Result<(), failure::Error> {
+    let mut contents = fs::read(path)?;
+
+    let (offset, big_endian) = {
+        let elf = ElfFile::new(&contents).map_err(failure::err_msg)?;
+        let symtab = match elf.find_section_by_name(".symtab") {
+            Some(symtab) => symtab,
+            None => return Ok(()),
+        };
+
+        let (shndx, address) = match symtab.get_data(&elf).map_err(failure::err_msg)? {
+            SectionData::SymbolTable32(entries) => {
+                match entries
+                    .iter()
+                    .find(|entry| entry.get_name(&elf) == Ok(SYMBOL))
+                {
+                    Some(entry) => (entry.shndx(), entry.value()),
+                    None => return Ok(()),
+                }
+            }
+
+            SectionData::SymbolTable64(_) => bail!("64-bit ELF files are not currently supported"),
+
+            _ => bail!("malformed .symtab section"),
+        };
+
+        let sh = elf.section_header(shndx).map_err(failure::err_msg)?;
+        ensure!(
+            sh.get_type() == Ok(ShType::ProgBits)
+                && address >= sh.address()
+                && address + 4 <= sh.address() + sh.size(),
+            "({}) `{}` is not part of the load image",
+            path.display(),
+            SYMBOL
+        );
+
+        (
+            (sh.offset() + (address - sh.address())) as usize,
+            matches!(elf.header.pt1.data(), header::Data::BigEndian),
+        )
+    };
+
+    let bytes = if big_endian {
+        hash.to_be_bytes()
+    } else {
+        hash.to_le_bytes()
+    };
+
+    if contents[offset..offset + 4] != bytes {
+        contents[offset..offset + 4].copy_from_slice(&bytes);
+        fs::write(path, contents)?;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,182 @@
+use core::convert::TryInto;
+use std::{collections::BTreeMap, path::Path};
+
+use failure::bail;
+
+/// Name of the section of the data object that holds how the shared variables are accessed
+const SECTION: &str = ".microamp.access";
+
+/// How a shared variable is accessed, as recorded by the `#[shared]` attribute
+///
+/// The records are only emitted when the cores are compiled for different targets
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct Access {
+    /// The sizes of the atomics the type of the variable contains, as a bitmask
+    atomics: u8,
+    /// What the target of the data object supports
+    target: Target,
+    /// The cores that can access the variable; empty means all of them
+    cores: Vec<usize>,
+}
+
+/// The properties of a target that shared data depends on
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) struct Target {
+    /// The sizes of the atomics the target can operate on, as a bitmask
+    atomics: u8,
+    big_endian: bool,
+    /// In bytes
+    pointer_width: u8,
+}
+
+/// Reads the raw access records from the (unstripped) data object at `path`
+pub(crate) fn records(path: &Path) -> Result<Vec<u8>, failure::Error> {
+    crate::section_data(path, SECTION)
+}
+
+/// Parses the access records returned by [`records`]
+///
+/// Returns the records indexed by symbol
+pub(crate) fn parse_records(mut bytes: &[u8]) -> Result<BTreeMap<String, Access>, failure::Error> {
+    let mut accesses = BTreeMap::new();
+    while !bytes.is_empty() {
+        let (name, access) = parse(&mut bytes)?;
+        accesses.insert(name, access);
+    }
+
+    Ok(accesses)
+}
+
+/// Parses one record; see `microamp::export::access` for the format
+fn parse(bytes: &mut &[u8]) -> Result<(String, Access), failure::Error> {
+    let len = usize::from(u16::from_le_bytes(take(bytes)?));
+    if bytes.len() < len {
+        bail!("malformed {} section", SECTION);
+    }
+    let name = String::from_utf8_lossy(&bytes[..len]).into_owned();
+    *bytes = &bytes[len..];
+
+    let [atomics, target_atomics, big_endian, pointer_width] = take(bytes)?;
+    let n = u16::from_le_bytes(take(bytes)?);
+
+    let mut cores = vec![];
+    for _ in 0..n {
+        cores.push(u32::from_le_bytes(take(bytes)?) as usize);
+    }
+
+    Ok((
+        name,
+        Access {
+            atomics,
+            target: Target {
+                atomics: target_atomics,
+                big_endian: big_endian != 0,
+                pointer_width,
+            },
+            cores,
+        },
+    ))
+}
+
+fn take<const N: usize>(bytes: &mut &[u8]) -> Result<[u8; N], failure::Error> {
+    if bytes.len() < N {
+        bail!("malformed {} section", SECTION);
+    }
+
+    let (head, tail) = bytes.split_at(N);
+    *bytes = tail;
+    Ok(head.try_into().expect("unreachable"))
+}
+
+/// Checks that every core that can access a shared variable can operate on its atomics, and that
+/// all those cores agree on endianness and pointer width
+///
+/// `cores` are the name of the target of each core, in core order, and the access records of the
+/// data object the core uses
+pub(crate) fn check(cores: &[(&str, &BTreeMap<String, Access>)]) -> Result<(), failure::Error> {
+    let symbols = cores.iter().flat_map(|(_, accesses)| accesses.iter());
+
+    for (symbol, access) in symbols {
+        // (core, name of its target, what the target supports)
+        let mut participants = vec![];
+        for (i, (name, accesses)) in cores.iter().enumerate() {
+            if !access.cores.is_empty() && !access.cores.contains(&i) {
+                continue;
+            }
+
+            if let Some(target) = accesses.get(symbol) {
+                participants.push((i, name, target.target));
+            }
+        }
+
+        for (i, name, target) in &participants {
+            let missing = access.atomics & !target.atomics;
+            if missing != 0 {
+                bail!(
+                    "core {} ({}) can't operate on the atomics of `{}`: its target has no {} \
+                     atomics",
+                    i,
+                    name,
+                    symbol,
+                    widths(missing)
+                );
+            }
+        }
+
+        if let Some((first, rest)) = participants.split_first() {
+            let (i, name, target) = first;
+            for (j, other, found) in rest {
+                if target.big_endian != found.big_endian {
+                    bail!(
+                        "`{}` is shared between core {} ({}), which is {} endian, and core {} \
+                         ({}), which is {} endian",
+                        symbol,
+                        i,
+                        name,
+                        endianness(target.big_endian),
+                        j,
+                        other,
+                        endianness(found.big_endian)
+                    );
+                }
+
+                if target.pointer_width != found.pointer_width {
+                    bail!(
+                        "`{}` is shared between core {} ({}), which has {}-bit pointers, and \
+                         core {} ({}), which has {}-bit pointers",
+                        symbol,
+                        i,
+                        name,
+                        u32::from(target.pointer_width) * 8,
+                        j,
+                        other,
+                        u32::from(found.pointer_width) * 8
+                    );
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Formats a bitmask of atomic sizes as a list of widths in bits, e.g. "8-bit and 16-bit"
+fn widths(mask: u8) -> String {
+    let widths = (0..4)
+        .filter(|bit| mask & (1 << bit) != 0)
+        .map(|bit| format!("{}-bit", 8 << bit))
+        .collect::<Vec<_>>();
+
+    match widths.split_last() {
+        Some((last, rest)) if !rest.is_empty() => format!("{} and {}", rest.join(", "), last),
+        _ => widths.concat(),
+    }
+}
+
+fn endianness(big_endian: bool) -> &'static str {
+    if big_endian {
+        "big"
+    } else {
+        "little"
+    }
+}
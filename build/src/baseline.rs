@@ -0,0 +1,685 @@
+use core::fmt;
+use std::{collections::BTreeMap, fs, path::Path};
+
+use failure::{bail, format_err, ResultExt};
+
+use crate::Layout;
+
+/// First line of a baseline file
+const HEADER: &str = "# microamp shared layout; generated by `cargo microamp abi-check --update`";
+
+/// The layout of the shared sections of a build, in a form that can be committed and compared
+/// against later builds
+///
+/// Records the start address of each shared section and, for each shared variable, its section,
+/// offset, size and the fingerprint of its type. The start addresses are informational: cores may
+/// map the shared memory at different addresses, so only the offsets must stay the same
+#[derive(Clone, Debug, PartialEq)]
+pub struct Baseline {
+    /// section name -> start address
+    sections: BTreeMap<String, u64>,
+    /// symbol name -> placement
+    symbols: BTreeMap<String, Placement>,
+}
+
+/// Where a shared variable is placed
+#[derive(Clone, Debug, PartialEq)]
+struct Placement {
+    section: String,
+    offset: u64,
+    size: u64,
+    fingerprint: Option<u64>,
+}
+
+impl Baseline {
+    /// Records the (verified) `layout`
+    ///
+    /// Symbols without a name can't be tracked across builds and are left out
+    pub fn new(layout: &Layout) -> Self {
+        let mut sections = BTreeMap::new();
+        let mut symbols = BTreeMap::new();
+        for (section, entries) in layout.sections() {
+            sections.insert(section.clone(), layout.address(section).unwrap_or(0));
+
+            for (offset, symbol) in entries {
+                if let Some(name) = symbol.name() {
+                    symbols.insert(
+                        name.to_owned(),
+                        Placement {
+                            section: section.clone(),
+                            offset,
+                            size: symbol.size(),
+                            fingerprint: layout.fingerprint(name),
+                        },
+                    );
+                }
+            }
+        }
+
+        Baseline { sections, symbols }
+    }
+
+    /// Reads the baseline file at `path`
+    pub fn load(path: &Path) -> Result<Self, failure::Error> {
+        let contents = fs::read_to_string(path)
+            .with_context(|_| format!("couldn't read the baseline `{}`", path.display()))?;
+
+        let baseline = contents
+            .parse::<Baseline>()
+            .with_context(|_| format!("malformed baseline `{}`", path.display()))?;
+        Ok(baseline)
+    }
+
+    /// Writes the baseline to `path`
+    pub fn store(&self, path: &Path) -> Result<(), failure::Error> {
+        fs::write(path, self.to_string())?;
+        Ok(())
+    }
+
+    /// Lists the changes from this baseline to the `current` one
+    pub fn compare(&self, current: &Baseline) -> Vec<Change> {
+        let mut changes = vec![];
+
+        for (section, from) in &self.sections {
+            let kind = match current.sections.get(section) {
+                None => ChangeKind::SectionRemoved,
+                Some(to) if from != to => ChangeKind::SectionMoved {
+                    from: *from,
+                    to: *to,
+                },
+                Some(_) => continue,
+            };
+
+            changes.push(Change {
+                name: section.clone(),
+                kind,
+            });
+        }
+
+        for (name, old) in &self.symbols {
+            let kind = match current.symbols.get(name) {
+                None => ChangeKind::Removed,
+                Some(new) if (&old.section, old.offset) != (&new.section, new.offset) => {
+                    ChangeKind::Moved {
+                        from: (old.section.clone(), old.offset),
+                        to: (new.section.clone(), new.offset),
+                    }
+                }
+                Some(new) if old.size != new.size => ChangeKind::Resized {
+                    from: old.size,
+                    to: new.size,
+                },
+                Some(new) => match (old.fingerprint, new.fingerprint) {
+                    (Some(from), Some(to)) if from != to => ChangeKind::Retyped { from, to },
+                    _ => continue,
+                },
+            };
+
+            changes.push(Change {
+                name: name.clone(),
+                kind,
+            });
+        }
+
+        for (name, new) in &current.symbols {
+            if !self.symbols.contains_key(name) {
+                let section = new.section.clone();
+                let offset = new.offset;
+                // a variable that doesn't fit in the section of the baseline makes it larger
+                let kind = if new.offset + new.size > self.end(&new.section) {
+                    ChangeKind::Appended { section, offset }
+                } else {
+                    ChangeKind::Added { section, offset }
+                };
+
+                changes.push(Change {
+                    name: name.clone(),
+                    kind,
+                });
+            }
+        }
+
+        changes
+    }
+
+    /// The offset right after the last variable of `section`; 0 if the section doesn't exist
+    fn end(&self, section: &str) -> u64 {
+        self.symbols
+            .values()
+            .filter(|placement| placement.section == section)
+            .map(|placement| placement.offset + placement.size)
+            .max()
+            .unwrap_or(0)
+    }
+}
+
+impl fmt::Display for Baseline {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{}", HEADER)?;
+
+        for (section, address) in &self.sections {
+            writeln!(f, "section {} {:#x}", section, address)?;
+
+            let mut symbols = self
+                .symbols
+                .iter()
+                .filter(|(_, placement)| placement.section == *section)
+                .collect::<Vec<_>>();
+            symbols.sort_by_key(|(_, placement)| placement.offset);
+
+            for (name, placement) in symbols {
+                write!(
+                    f,
+                    "symbol {} {:#x} {} ",
+                    name, placement.offset, placement.size
+                )?;
+                match placement.fingerprint {
+                    Some(fingerprint) => writeln!(f, "{:#010x}", fingerprint)?,
+                    None => writeln!(f, "-")?,
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl core::str::FromStr for Baseline {
+    type Err = failure::Error;
+
+    fn from_str(s: &str) -> Result<Self, failure::Error> {
+        let mut sections = BTreeMap::new();
+        let mut symbols = BTreeMap::new();
+        // the section the `symbol` lines belong to
+        let mut current = None;
+
+        for (i, line) in s.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let fields = line.split_whitespace().collect::<Vec<_>>();
+            match &fields[..] {
+                ["section", name, address] => {
+                    sections.insert((*name).to_owned(), number(address, i)?);
+                    current = Some(*name);
+                }
+
+                ["symbol", name, offset, size, fingerprint] => {
+                    let section = match current {
+                        Some(section) => section,
+                        None => bail!("line {}: `symbol` before any `section`", i + 1),
+                    };
+                    let fingerprint = if *fingerprint == "-" {
+                        None
+                    } else {
+                        Some(number(fingerprint, i)?)
+                    };
+
+                    symbols.insert(
+                        (*name).to_owned(),
+                        Placement {
+                            section: section.to_owned(),
+                            offset: number(offset, i)?,
+                            size: number(size, i)?,
+                            fingerprint,
+                        },
+                    );
+                }
+
+                _ => bail!("line {}: unrecognized entry `{}`", i + 1, line),
+            }
+        }
+
+        Ok(Baseline { sections, symbols })
+    }
+}
+
+/// Parses a decimal or `0x`-prefixed hexadecimal number on line `i` (0-based)
+fn number(s: &str, i: usize) -> Result<u64, failure::Error> {
+    let res = if let Some(hex) = s.strip_prefix("0x") {
+        u64::from_str_radix(hex, 16)
+    } else {
+        s.parse()
+    };
+
+    res.map_err(|_| format_err!("line {}: `{}` is not a number", i + 1, s))
+}
+
+/// A difference between a baseline and a later build
+#[derive(Clone, Debug, PartialEq)]
+pub struct Change {
+    /// The symbol of the shared variable; the name of the section for `SectionMoved` and
+    /// `SectionRemoved`
+    pub name: String,
+    /// What changed
+    pub kind: ChangeKind,
+}
+
+impl Change {
+    /// Whether firmware built against the baseline can't share memory with the later build
+    ///
+    /// Only adding variables, and moving sections, is compatible
+    pub fn is_breaking(&self) -> bool {
+        !matches!(
+            self.kind,
+            ChangeKind::Added { .. }
+                | ChangeKind::Appended { .. }
+                | ChangeKind::SectionMoved { .. }
+        )
+    }
+
+    /// Whether the change makes a section larger than it is in the baseline
+    ///
+    /// The change is compatible, but the memory map of the deployed firmware must have room for
+    /// the larger section
+    pub fn grows_section(&self) -> bool {
+        matches!(self.kind, ChangeKind::Appended { .. })
+    }
+}
+
+impl fmt::Display for Change {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = &self.name;
+        match &self.kind {
+            ChangeKind::Added { section, offset } => write!(
+                f,
+                "`{}` was added at offset {:#x} of `{}`",
+                name, offset, section
+            ),
+            ChangeKind::Appended { section, offset } => write!(
+                f,
+                "`{}` was added at offset {:#x} of `{}`, past its end in the baseline",
+                name, offset, section
+            ),
+            ChangeKind::Removed => write!(f, "`{}` was removed", name),
+            ChangeKind::Moved { from, to } => write!(
+                f,
+                "`{}` moved from offset {:#x} of `{}` to offset {:#x} of `{}`",
+                name, from.1, from.0, to.1, to.0
+            ),
+            ChangeKind::Resized { from, to } => {
+                write!(f, "`{}` was resized from {} to {} bytes", name, from, to)
+            }
+            ChangeKind::Retyped { from, to } => write!(
+                f,
+                "the type of `{}` changed (fingerprint {:#010x} to {:#010x})",
+                name, from, to
+            ),
+            ChangeKind::SectionMoved { from, to } => write!(
+                f,
+                "the `{}` section moved from {:#x} to {:#x}",
+                name, from, to
+            ),
+            ChangeKind::SectionRemoved => write!(f, "the `{}` section was removed", name),
+        }
+    }
+}
+
+/// The kinds of [`Change`]
+#[derive(Clone, Debug, PartialEq)]
+pub enum ChangeKind {
+    /// A new variable, placed in unused space of its section; compatible
+    Added {
+        /// The section the variable was placed in
+        section: String,
+        /// The offset of the variable from the start of the section
+        offset: u64,
+    },
+    /// A new variable that ends past the end of its section in the baseline, or that is in a new
+    /// section; compatible if the memory map of the deployed firmware has room for the larger
+    /// section
+    Appended {
+        /// The section the variable was placed in
+        section: String,
+        /// The offset of the variable from the start of the section
+        offset: u64,
+    },
+    /// The variable no longer exists
+    Removed,
+    /// The variable is at a different `(section, offset)`
+    Moved {
+        /// The old placement
+        from: (String, u64),
+        /// The new placement
+        to: (String, u64),
+    },
+    /// The variable has a different size, in bytes
+    Resized {
+        /// The old size
+        from: u64,
+        /// The new size
+        to: u64,
+    },
+    /// The variable has the same placement and size but its type has a different fingerprint
+    Retyped {
+        /// The old fingerprint
+        from: u64,
+        /// The new fingerprint
+        to: u64,
+    },
+    /// The section starts at a different address; compatible, as long as the deployed firmware
+    /// maps the section at its own address
+    SectionMoved {
+        /// The old address
+        from: u64,
+        /// The new address
+        to: u64,
+    },
+    /// The section no longer exists
+    SectionRemoved,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Baseline, Change, ChangeKind};
+
+    const BASELINE: &str = "\
+# microamp shared layout; generated by `cargo microamp abi-check --update`
+section .shared 0x30000000
+symbol app::X 0x0 1 0x12345678
+symbol app::Y 0x4 4 -
+symbol app::BUFFER 0x8 64 0x0badcafe
+section .shared.tcm 0x30001000
+symbol app::TCM 0x0 16 0xdeadbeef
+";
+
+    fn baseline(s: &str) -> Baseline {
+        s.parse().unwrap()
+    }
+
+    fn changes(current: &str) -> Vec<Change> {
+        baseline(BASELINE).compare(&baseline(current))
+    }
+
+    #[test]
+    fn round_trip() {
+        let parsed = baseline(BASELINE);
+
+        assert_eq!(parsed.to_string(), BASELINE);
+        assert_eq!(baseline(&parsed.to_string()), parsed);
+    }
+
+    #[test]
+    fn parse() {
+        let parsed = baseline(
+            "
+            # comments and blank lines are ignored
+
+            section .shared 1024
+            symbol app::X 0 4 -
+            ",
+        );
+
+        assert_eq!(parsed.sections[".shared"], 1024);
+        let x = &parsed.symbols["app::X"];
+        assert_eq!((x.section.as_str(), x.offset, x.size), (".shared", 0, 4));
+        assert_eq!(x.fingerprint, None);
+    }
+
+    #[test]
+    fn parse_errors() {
+        for s in &[
+            "symbol app::X 0x0 4 -",
+            "section .shared 0x30000000\nsymbol app::X 0x0 four -",
+            "section .shared 0xgg",
+            "section .shared",
+            "variable app::X",
+        ] {
+            assert!(s.parse::<Baseline>().is_err(), "{:?} was accepted", s);
+        }
+    }
+
+    #[test]
+    fn unchanged() {
+        assert_eq!(changes(BASELINE), vec![]);
+    }
+
+    #[test]
+    fn added() {
+        // in the padding between `X` and `Y`
+        let changes =
+            changes(&BASELINE.replace("symbol app::Y", "symbol app::FLAG 0x1 1 -\nsymbol app::Y"));
+
+        assert_eq!(
+            changes,
+            vec![Change {
+                name: "app::FLAG".to_owned(),
+                kind: ChangeKind::Added {
+                    section: ".shared".to_owned(),
+                    offset: 0x1,
+                },
+            }]
+        );
+        assert!(!changes[0].is_breaking());
+        assert!(!changes[0].grows_section());
+    }
+
+    #[test]
+    fn appended() {
+        let changes = changes(&format!(
+            "{}symbol app::NEW 0x10 4 0x00000001\nsection .shared.sram 0x60000000\n\
+             symbol app::LOG 0x0 64 -\n",
+            BASELINE
+        ));
+
+        assert_eq!(
+            changes,
+            vec![
+                Change {
+                    name: "app::LOG".to_owned(),
+                    kind: ChangeKind::Appended {
+                        section: ".shared.sram".to_owned(),
+                        offset: 0x0,
+                    },
+                },
+                Change {
+                    name: "app::NEW".to_owned(),
+                    kind: ChangeKind::Appended {
+                        section: ".shared.tcm".to_owned(),
+                        offset: 0x10,
+                    },
+                },
+            ]
+        );
+        assert!(changes.iter().all(|change| !change.is_breaking()));
+        assert!(changes.iter().all(|change| change.grows_section()));
+        assert_eq!(
+            changes[1].to_string(),
+            "`app::NEW` was added at offset 0x10 of `.shared.tcm`, past its end in the baseline"
+        );
+    }
+
+    #[test]
+    fn removed() {
+        let changes = changes(&BASELINE.replace("symbol app::Y 0x4 4 -\n", ""));
+
+        assert_eq!(
+            changes,
+            vec![Change {
+                name: "app::Y".to_owned(),
+                kind: ChangeKind::Removed,
+            }]
+        );
+        assert!(changes[0].is_breaking());
+    }
+
+    #[test]
+    fn moved() {
+        let changes = changes(&BASELINE.replace("app::Y 0x4", "app::Y 0x40"));
+
+        assert_eq!(
+            changes,
+            vec![Change {
+                name: "app::Y".to_owned(),
+                kind: ChangeKind::Moved {
+                    from: (".shared".to_owned(), 0x4),
+                    to: (".shared".to_owned(), 0x40),
+                },
+            }]
+        );
+        assert!(changes[0].is_breaking());
+    }
+
+    #[test]
+    fn moved_to_another_section() {
+        let current = BASELINE
+            .replace("symbol app::X 0x0 1 0x12345678\n", "")
+            .replace(
+                "symbol app::TCM",
+                "symbol app::X 0x10 1 0x12345678\nsymbol app::TCM",
+            );
+
+        assert_eq!(
+            changes(&current),
+            vec![Change {
+                name: "app::X".to_owned(),
+                kind: ChangeKind::Moved {
+                    from: (".shared".to_owned(), 0x0),
+                    to: (".shared.tcm".to_owned(), 0x10),
+                },
+            }]
+        );
+    }
+
+    #[test]
+    fn resized() {
+        let changes = changes(&BASELINE.replace("app::BUFFER 0x8 64", "app::BUFFER 0x8 128"));
+
+        assert_eq!(
+            changes,
+            vec![Change {
+                name: "app::BUFFER".to_owned(),
+                kind: ChangeKind::Resized { from: 64, to: 128 },
+            }]
+        );
+        assert!(changes[0].is_breaking());
+    }
+
+    #[test]
+    fn retyped() {
+        let changes = changes(&BASELINE.replace("0x0badcafe", "0x0badf00d"));
+
+        assert_eq!(
+            changes,
+            vec![Change {
+                name: "app::BUFFER".to_owned(),
+                kind: ChangeKind::Retyped {
+                    from: 0x0bad_cafe,
+                    to: 0x0bad_f00d,
+                },
+            }]
+        );
+        assert!(changes[0].is_breaking());
+    }
+
+    #[test]
+    fn respelled_type() {
+        use microamp::export::{fingerprint, Probe};
+
+        mod ipc {
+            #[derive(microamp::DataNotCode)]
+            #[repr(C)]
+            pub struct Msg {
+                pub len: u16,
+                pub tag: u16,
+            }
+        }
+
+        mod old {
+            #[derive(microamp::DataNotCode)]
+            #[repr(C)]
+            pub struct Msg {
+                pub tag: u16,
+                pub len: u16,
+            }
+        }
+
+        use ipc::Msg;
+
+        // the fingerprint `#[shared] static BUFFER: T` embeds
+        macro_rules! fingerprint {
+            ($T:ty) => {
+                u64::from(fingerprint::<$T>(Probe::<$T>::NAME, Probe::<$T>::FIELDS))
+            };
+        }
+
+        let with = |fingerprint: u64| {
+            baseline(&BASELINE.replace("0x0badcafe", &format!("{:#010x}", fingerprint)))
+        };
+
+        // `Msg` became `ipc::Msg`
+        let before = with(fingerprint!(Msg));
+        assert!(before.compare(&with(fingerprint!(ipc::Msg))).is_empty());
+
+        // the fields were reordered
+        let from = fingerprint!(old::Msg);
+        let to = fingerprint!(ipc::Msg);
+        assert_eq!(
+            with(from).compare(&with(to)),
+            vec![Change {
+                name: "app::BUFFER".to_owned(),
+                kind: ChangeKind::Retyped { from, to },
+            }]
+        );
+    }
+
+    #[test]
+    fn missing_fingerprints_are_not_compared() {
+        let current = BASELINE
+            .replace("app::Y 0x4 4 -", "app::Y 0x4 4 0x00000001")
+            .replace("0x0badcafe", "-");
+
+        assert_eq!(changes(&current), vec![]);
+    }
+
+    #[test]
+    fn section_moved() {
+        let changes =
+            changes(&BASELINE.replace(".shared.tcm 0x30001000", ".shared.tcm 0x30002000"));
+
+        assert_eq!(
+            changes,
+            vec![Change {
+                name: ".shared.tcm".to_owned(),
+                kind: ChangeKind::SectionMoved {
+                    from: 0x3000_1000,
+                    to: 0x3000_2000,
+                },
+            }]
+        );
+        assert!(!changes[0].is_breaking());
+    }
+
+    #[test]
+    fn section_removed() {
+        let current = &BASELINE[..BASELINE.find("section .shared.tcm").unwrap()];
+        let changes = changes(current);
+
+        assert_eq!(
+            changes,
+            vec![
+                Change {
+                    name: ".shared.tcm".to_owned(),
+                    kind: ChangeKind::SectionRemoved,
+                },
+                Change {
+                    name: "app::TCM".to_owned(),
+                    kind: ChangeKind::Removed,
+                },
+            ]
+        );
+        assert!(changes.iter().all(Change::is_breaking));
+    }
+
+    #[test]
+    fn display() {
+        let change = Change {
+            name: ".shared.tcm".to_owned(),
+            kind: ChangeKind::SectionRemoved,
+        };
+
+        assert_eq!(change.to_string(), "the `.shared.tcm` section was removed");
+    }
+}
@@ -0,0 +1,235 @@
+use core::{convert::TryInto, fmt};
+use std::{collections::BTreeMap, path::Path};
+
+use failure::{bail, Fail};
+
+/// Name of the section of the data object that holds the layouts of the shared types
+const SECTION: &str = ".microamp.layout";
+
+/// The layout of a shared variable, or of one of its fields, as recorded by the `#[shared]`
+/// attribute
+///
+/// The layouts are only recorded when the cores are compiled for different targets
+#[derive(Clone, Debug, PartialEq)]
+pub struct FieldLayout {
+    name: String,
+    offset: u64,
+    size: u64,
+    align: u64,
+    fields: Vec<FieldLayout>,
+}
+
+impl FieldLayout {
+    /// The name of the field; the symbol of the variable for a top level layout
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// The offset of the field from the start of its parent, in bytes
+    pub fn offset(&self) -> u64 {
+        self.offset
+    }
+
+    /// The size of the field in bytes
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    /// The alignment of the field in bytes
+    pub fn align(&self) -> u64 {
+        self.align
+    }
+
+    /// The fields of the field's type, in declaration order
+    pub fn fields(&self) -> &[FieldLayout] {
+        &self.fields
+    }
+}
+
+impl fmt::Display for FieldLayout {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "at offset {} with size {} and alignment {}",
+            self.offset, self.size, self.align
+        )
+    }
+}
+
+/// Reads the layouts of the shared variables from the (unstripped) data object at `path`
+///
+/// Returns the layouts indexed by symbol
+pub fn field_layouts(path: &Path) -> Result<BTreeMap<String, FieldLayout>, failure::Error> {
+    parse_records(&records(path)?)
+}
+
+/// Reads the raw layout records from the (unstripped) data object at `path`
+pub(crate) fn records(path: &Path) -> Result<Vec<u8>, failure::Error> {
+    crate::section_data(path, SECTION)
+}
+
+/// Parses the layout records returned by [`records`]
+///
+/// Returns the layouts indexed by symbol
+pub(crate) fn parse_records(
+    mut bytes: &[u8],
+) -> Result<BTreeMap<String, FieldLayout>, failure::Error> {
+    let mut layouts = BTreeMap::new();
+    while !bytes.is_empty() {
+        let layout = parse(&mut bytes)?;
+        layouts.insert(layout.name.clone(), layout);
+    }
+
+    Ok(layouts)
+}
+
+/// Parses one record; see `microamp::export::layout` for the format
+fn parse(bytes: &mut &[u8]) -> Result<FieldLayout, failure::Error> {
+    let len = usize::from(u16::from_le_bytes(take(bytes)?));
+    if bytes.len() < len {
+        bail!("malformed {} section", SECTION);
+    }
+    let name = String::from_utf8_lossy(&bytes[..len]).into_owned();
+    *bytes = &bytes[len..];
+
+    let offset = u64::from(u32::from_le_bytes(take(bytes)?));
+    let size = u64::from(u32::from_le_bytes(take(bytes)?));
+    let align = u64::from(u32::from_le_bytes(take(bytes)?));
+    let n = u16::from_le_bytes(take(bytes)?);
+
+    let mut fields = vec![];
+    for _ in 0..n {
+        fields.push(parse(bytes)?);
+    }
+
+    Ok(FieldLayout {
+        name,
+        offset,
+        size,
+        align,
+        fields,
+    })
+}
+
+fn take<const N: usize>(bytes: &mut &[u8]) -> Result<[u8; N], failure::Error> {
+    if bytes.len() < N {
+        bail!("malformed {} section", SECTION);
+    }
+
+    let (head, tail) = bytes.split_at(N);
+    *bytes = tail;
+    Ok(head.try_into().expect("unreachable"))
+}
+
+/// A shared variable has a different layout in the data objects of two cores
+#[derive(Debug)]
+pub struct FieldMismatch {
+    /// The path to the first field whose layout differs, starting with the symbol of the variable
+    pub path: String,
+    /// The core the others are compared against, and the layout of the field on it
+    pub expected: (usize, Option<FieldLayout>),
+    /// The core whose layout doesn't match, and the layout of the field on it
+    pub found: (usize, Option<FieldLayout>),
+}
+
+impl fmt::Display for FieldMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fn describe(layout: &Option<FieldLayout>) -> String {
+            match layout {
+                Some(layout) => layout.to_string(),
+                None => "missing".to_owned(),
+            }
+        }
+
+        write!(
+            f,
+            "the layout of `{}` differs between cores {} and {}: it's {} on core {} but {} on \
+             core {}",
+            self.path,
+            self.expected.0,
+            self.found.0,
+            describe(&self.expected.1),
+            self.expected.0,
+            describe(&self.found.1),
+            self.found.0,
+        )
+    }
+}
+
+impl Fail for FieldMismatch {}
+
+/// Checks that the shared variables have the same layout in the data objects of all the cores
+///
+/// `objects` are `(core, layouts)` pairs, one per distinct data object. If the layouts differ the
+/// error is a [`FieldMismatch`]
+pub(crate) fn compare(
+    objects: &[(usize, BTreeMap<String, FieldLayout>)],
+) -> Result<(), failure::Error> {
+    let (base_core, base) = match objects.first() {
+        Some(first) => first,
+        None => return Ok(()),
+    };
+
+    for (core, layouts) in &objects[1..] {
+        let symbols = base.keys().chain(layouts.keys());
+        for symbol in symbols {
+            let expected = base.get(symbol);
+            let found = layouts.get(symbol);
+
+            if let Some((path, expected, found)) = diverging(symbol, expected, found) {
+                return Err(FieldMismatch {
+                    path,
+                    expected: (*base_core, expected.cloned()),
+                    found: (*core, found.cloned()),
+                }
+                .into());
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Finds the first field, in declaration order, whose layout differs
+#[allow(clippy::type_complexity)]
+fn diverging<'a>(
+    path: &str,
+    expected: Option<&'a FieldLayout>,
+    found: Option<&'a FieldLayout>,
+) -> Option<(String, Option<&'a FieldLayout>, Option<&'a FieldLayout>)> {
+    let (e, f) = match (expected, found) {
+        (Some(e), Some(f)) => (e, f),
+        (None, None) => return None,
+        _ => return Some((path.to_owned(), expected, found)),
+    };
+
+    if (e.offset, e.size, e.align) != (f.offset, f.size, f.align) {
+        return Some((path.to_owned(), expected, found));
+    }
+
+    for i in 0..e.fields.len().max(f.fields.len()) {
+        let (expected, found) = (e.fields.get(i), f.fields.get(i));
+        let name = expected
+            .or(found)
+            .map(|field| &field.name[..])
+            .unwrap_or("");
+        let child = if name.starts_with('[') {
+            format!("{}{}", path, name)
+        } else {
+            format!("{}.{}", path, name)
+        };
+
+        // a field that was renamed is as good as missing
+        if let (Some(e), Some(f)) = (expected, found) {
+            if e.name != f.name {
+                return Some((child, expected, None));
+            }
+        }
+
+        if let Some(diverging) = diverging(&child, expected, found) {
+            return Some(diverging);
+        }
+    }
+
+    None
+}
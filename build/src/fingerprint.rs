@@ -0,0 +1,382 @@
+use std::{
+    collections::{hash_map::DefaultHasher, BTreeMap},
+    env, fs,
+    hash::{Hash, Hasher},
+    path::{Path, PathBuf},
+};
+
+use failure::format_err;
+use walkdir::WalkDir;
+
+/// What an image was linked from
+pub(crate) struct Fingerprint {
+    /// Hash of the compiler version, target, flags, profile, features and linker scripts
+    pub(crate) settings: u64,
+    /// Hash of the stripped data object
+    pub(crate) data: u64,
+    /// Hash of the contents of `inputs`
+    pub(crate) sources: u64,
+    /// Source files the image was compiled from, as reported by the compiler
+    pub(crate) inputs: Vec<PathBuf>,
+    /// Layout records of the data object, as found in its `.microamp.layout` sections
+    pub(crate) layouts: Vec<u8>,
+    /// Access records of the data object, as found in its `.microamp.access` sections
+    pub(crate) accesses: Vec<u8>,
+}
+
+impl Fingerprint {
+    /// Loads a fingerprint; returns `None` if it, or the records stored next to it, are missing or
+    /// unreadable
+    pub(crate) fn load(path: &Path) -> Option<Self> {
+        let contents = fs::read_to_string(path).ok()?;
+        let layouts = fs::read(sibling(path, "layout")).ok()?;
+        let accesses = fs::read(sibling(path, "access")).ok()?;
+        let mut lines = contents.lines();
+        let mut hash = || u64::from_str_radix(lines.next()?, 16).ok();
+        let settings = hash()?;
+        let data = hash()?;
+        let sources = hash()?;
+
+        Some(Fingerprint {
+            settings,
+            data,
+            sources,
+            inputs: lines.map(PathBuf::from).collect(),
+            layouts,
+            accesses,
+        })
+    }
+
+    pub(crate) fn store(&self, path: &Path) -> Result<(), failure::Error> {
+        let mut contents = format!(
+            "{:016x}\n{:016x}\n{:016x}\n",
+            self.settings, self.data, self.sources
+        );
+        for input in &self.inputs {
+            let input = input
+                .to_str()
+                .ok_or_else(|| format_err!("{} is not valid UTF-8", input.display()))?;
+            contents.push_str(input);
+            contents.push('\n');
+        }
+
+        fs::create_dir_all(path.parent().expect("unreachable"))?;
+        fs::write(sibling(path, "layout"), &self.layouts)?;
+        fs::write(sibling(path, "access"), &self.accesses)?;
+        fs::write(path, contents)?;
+        Ok(())
+    }
+
+    /// Removes the fingerprint and the records stored next to it
+    pub(crate) fn remove(path: &Path) -> Result<(), failure::Error> {
+        for path in &[
+            sibling(path, "layout"),
+            sibling(path, "access"),
+            path.to_owned(),
+        ] {
+            if path.exists() {
+                fs::remove_file(path)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Whether the image is up to date with respect to the current `settings` and sources
+    pub(crate) fn is_fresh(&self, settings: u64) -> bool {
+        self.settings == settings && hash_files(&self.inputs).ok() == Some(self.sources)
+    }
+}
+
+/// The path of the file, next to the fingerprint at `path`, that has the given `extension`
+fn sibling(path: &Path, extension: &str) -> PathBuf {
+    let mut name = path.file_name().expect("unreachable").to_owned();
+    name.push(".");
+    name.push(extension);
+    path.with_file_name(name)
+}
+
+/// Hashes the contents of `paths`
+pub(crate) fn hash_files(paths: &[PathBuf]) -> Result<u64, failure::Error> {
+    let mut hasher = DefaultHasher::new();
+    for path in paths {
+        fs::read(path)?.hash(&mut hasher);
+    }
+    Ok(hasher.finish())
+}
+
+/// Hashes the linker scripts (`*.x` files) found in `dirs` and in the build script output
+/// directories within them, ignoring the scripts of cores other than `core`
+pub(crate) fn hash_linker_scripts(
+    dirs: &[&Path],
+    core: usize,
+    hasher: &mut DefaultHasher,
+) -> Result<(), failure::Error> {
+    let ours = format!("core{}", core);
+    let other_core =
+        |stem: &str| stem != ours && stem.starts_with("core") && stem[4..].parse::<usize>().is_ok();
+
+    for dir in dirs {
+        if !dir.exists() {
+            continue;
+        }
+
+        let mut scripts = vec![];
+        for entry in WalkDir::new(dir).max_depth(3) {
+            let entry = entry?;
+            let path = entry.path();
+
+            let is_script = path.extension().map(|ext| ext == "x").unwrap_or(false)
+                && !path
+                    .file_stem()
+                    .and_then(|stem| stem.to_str())
+                    .map(other_core)
+                    .unwrap_or(false);
+            let in_out_dir = entry.depth() == 1
+                || path
+                    .parent()
+                    .and_then(Path::file_name)
+                    .map(|name| name == "out")
+                    .unwrap_or(false);
+            if is_script && in_out_dir && entry.file_type().is_file() {
+                scripts.push(path.to_owned());
+            }
+        }
+        scripts.sort();
+
+        hasher.write_u64(hash_files(&scripts)?);
+    }
+
+    Ok(())
+}
+
+/// Hashes the Cargo files that affect the build of the project at `root`: the manifests (and
+/// their profiles), the lock file and the configuration files
+///
+/// Cargo looks for them in `root` and its ancestors, and for the configuration also in
+/// `$CARGO_HOME`. The compiler doesn't report the sources of registry dependencies as inputs; their
+/// versions are in the lock file
+pub(crate) fn hash_cargo_files(
+    root: &Path,
+    hasher: &mut DefaultHasher,
+) -> Result<(), failure::Error> {
+    let mut candidates = vec![];
+    for dir in root.ancestors() {
+        candidates.push(dir.join("Cargo.toml"));
+        candidates.push(dir.join("Cargo.lock"));
+        candidates.push(dir.join(".cargo/config"));
+        candidates.push(dir.join(".cargo/config.toml"));
+    }
+
+    let home = env::var_os("CARGO_HOME")
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".cargo")));
+    if let Some(home) = home {
+        candidates.push(home.join("config"));
+        candidates.push(home.join("config.toml"));
+    }
+
+    // NOTE the paths are hashed too so that adding or removing one of the files is a change
+    let files = candidates
+        .into_iter()
+        .filter(|path| path.is_file())
+        .collect::<Vec<_>>();
+    files.hash(hasher);
+    hasher.write_u64(hash_files(&files)?);
+
+    Ok(())
+}
+
+/// Hashes the environment variables that change how Cargo and the compiler build the images, like
+/// `RUSTFLAGS` and `CARGO_*`
+pub(crate) fn hash_env(hasher: &mut DefaultHasher) {
+    let vars = env::vars_os()
+        .filter(|(key, _)| {
+            key.to_str()
+                .map(|key| {
+                    (key.starts_with("CARGO_") && key != "CARGO_MAKEFLAGS")
+                        || key.starts_with("RUSTC_")
+                        || key == "RUSTFLAGS"
+                })
+                .unwrap_or(false)
+        })
+        .collect::<BTreeMap<_, _>>();
+
+    vars.hash(hasher);
+}
+
+/// Parses a Makefile-style dep-info file and returns the dependencies listed in it
+pub(crate) fn parse_dep_info(path: &Path) -> Result<Vec<PathBuf>, failure::Error> {
+    let contents = fs::read_to_string(path)?;
+    parse_deps(&contents).ok_or_else(|| format_err!("malformed dep-info file {}", path.display()))
+}
+
+/// Returns the dependencies of the first rule in the dep-info `contents`
+fn parse_deps(contents: &str) -> Option<Vec<PathBuf>> {
+    // a backslash at the end of a line continues the rule on the next line
+    let mut lines = contents
+        .lines()
+        .skip_while(|line| line.trim().is_empty() || line.starts_with('#'));
+    let mut rule = String::new();
+    for line in &mut lines {
+        let escapes = line.len() - line.trim_end_matches('\\').len();
+        if escapes % 2 == 1 {
+            rule.push_str(&line[..line.len() - 1]);
+            rule.push(' ');
+        } else {
+            rule.push_str(line);
+            break;
+        }
+    }
+
+    let deps = &rule[rule.find(": ")? + 2..];
+
+    let mut inputs = vec![];
+    let mut current = String::new();
+    let mut chars = deps.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            // spaces in paths are escaped
+            '\\' => current.extend(chars.next()),
+            ' ' | '\t' => {
+                if !current.is_empty() {
+                    inputs.push(PathBuf::from(&current));
+                    current.clear();
+                }
+            }
+            _ => current.push(c),
+        }
+    }
+    if !current.is_empty() {
+        inputs.push(PathBuf::from(current));
+    }
+
+    Some(inputs)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::hash_map::DefaultHasher, fs, hash::Hasher, path::PathBuf};
+
+    use tempdir::TempDir;
+
+    use super::{hash_cargo_files, parse_deps, Fingerprint};
+
+    fn paths(paths: &[&str]) -> Vec<PathBuf> {
+        paths.iter().map(PathBuf::from).collect()
+    }
+
+    #[test]
+    fn deps() {
+        assert_eq!(
+            parse_deps("/app/target/debug/app: /app/src/main.rs /app/src/ipc.rs\n"),
+            Some(paths(&["/app/src/main.rs", "/app/src/ipc.rs"]))
+        );
+    }
+
+    #[test]
+    fn deps_with_escaped_spaces() {
+        assert_eq!(
+            parse_deps("/my\\ app/target/debug/app: /my\\ app/src/main.rs /my\\ app/build.rs\n"),
+            Some(paths(&["/my app/src/main.rs", "/my app/build.rs"]))
+        );
+    }
+
+    #[test]
+    fn deps_with_continuations() {
+        let contents = "\
+# generated by Cargo
+
+/app/target/debug/app: /app/src/main.rs \\
+  /app/src/ipc.rs\\
+  /my\\ app/src/lib.rs
+
+/app/src/main.rs:
+";
+
+        assert_eq!(
+            parse_deps(contents),
+            Some(paths(&[
+                "/app/src/main.rs",
+                "/app/src/ipc.rs",
+                "/my app/src/lib.rs"
+            ]))
+        );
+    }
+
+    #[test]
+    fn deps_with_crlf() {
+        assert_eq!(
+            parse_deps("app: a.rs \\\r\n b.rs\r\n"),
+            Some(paths(&["a.rs", "b.rs"]))
+        );
+    }
+
+    #[test]
+    fn malformed_deps() {
+        assert_eq!(parse_deps(""), None);
+        assert_eq!(parse_deps("# only a comment\n"), None);
+        assert_eq!(parse_deps("no rule here\n"), None);
+    }
+
+    #[test]
+    fn round_trip() {
+        let td = TempDir::new("microamp-fingerprint").unwrap();
+        let path = td.path().join(".microamp/app-0");
+        let fp = Fingerprint {
+            settings: 0x0123_4567_89ab_cdef,
+            data: 1,
+            sources: u64::MAX,
+            inputs: paths(&["/app/src/main.rs", "/my app/src/ipc.rs"]),
+            layouts: vec![0, 1, 2, 255],
+            accesses: vec![],
+        };
+        fp.store(&path).unwrap();
+
+        let loaded = Fingerprint::load(&path).unwrap();
+        assert_eq!(loaded.settings, fp.settings);
+        assert_eq!(loaded.data, fp.data);
+        assert_eq!(loaded.sources, fp.sources);
+        assert_eq!(loaded.inputs, fp.inputs);
+        assert_eq!(loaded.layouts, fp.layouts);
+        assert_eq!(loaded.accesses, fp.accesses);
+
+        // the records are part of the fingerprint
+        fs::remove_file(td.path().join(".microamp/app-0.access")).unwrap();
+        assert!(Fingerprint::load(&path).is_none());
+
+        Fingerprint::remove(&path).unwrap();
+        assert!(fs::read_dir(td.path().join(".microamp"))
+            .unwrap()
+            .next()
+            .is_none());
+    }
+
+    #[test]
+    fn cargo_files() {
+        let td = TempDir::new("microamp-fingerprint").unwrap();
+        let root = td.path().join("app");
+        fs::create_dir_all(root.join(".cargo")).unwrap();
+        fs::write(root.join("Cargo.toml"), "[profile.dev]\nopt-level = 0\n").unwrap();
+
+        let hash = || {
+            let mut hasher = DefaultHasher::new();
+            hash_cargo_files(&root, &mut hasher).unwrap();
+            hasher.finish()
+        };
+
+        let manifest = hash();
+        fs::write(root.join("Cargo.toml"), "[profile.dev]\nopt-level = 1\n").unwrap();
+        let profile = hash();
+        assert_ne!(manifest, profile);
+
+        fs::write(root.join("Cargo.lock"), "version = 3\n").unwrap();
+        let lock = hash();
+        assert_ne!(profile, lock);
+
+        fs::write(root.join(".cargo/config.toml"), "[build]\nrustflags = []\n").unwrap();
+        let config = hash();
+        assert_ne!(lock, config);
+        assert_eq!(config, hash());
+    }
+}
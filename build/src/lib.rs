@@ -0,0 +1,705 @@
+//! The build pipeline of μAMP applications
+//!
+//! This is the library behind `cargo microamp`. An application is built in these steps:
+//!
+//! - The *data pass* compiles the application with `--cfg microamp`. The resulting object file
+//!   contains all the `#[shared]` variables.
+//! - The data object is *stripped* of everything but the shared sections (`.shared` and
+//!   `.shared.*`). The result is the `microamp-data.o` file that's passed to the linker.
+//! - The application is compiled and *linked* once per core, with `--cfg core="N"` and the
+//!   `coreN.x` linker script.
+//! - When the cores are compiled for different targets, the layouts of the shared types recorded
+//!   in the data objects are compared field by field, and every core that can access a shared
+//!   variable must be able to operate on its atomics and agree with the others on endianness and
+//!   pointer width.
+//! - The layout of each shared section is *verified* to be the same on all the images.
+//! - A hash of the verified layout is *embedded* in all the images so the cores can check at
+//!   runtime that they were built together.
+//!
+//! A [`Baseline`] of the verified layout can be committed and compared against later builds to
+//! catch changes that break compatibility with firmware that's already deployed.
+//!
+//! [`Pipeline::build`] runs all the steps and skips the images that are up to date.
+//!
+//! ``` no_run
+//! use microamp_build::{Artifact, Config, Pipeline};
+//!
+//! # fn main() -> Result<(), failure::Error> {
+//! let config = Config::new(Artifact::Bin("app".to_owned()));
+//! let build = Pipeline::new(config, ".")?.build()?;
+//!
+//! for image in &build.images {
+//!     println!("core {}: {}", image.core, image.path.display());
+//! }
+//! println!("{:#?}", build.layout);
+//! # Ok(())
+//! # }
+//! ```
+
+#![deny(missing_docs)]
+#![deny(rust_2018_compatibility)]
+#![deny(rust_2018_idioms)]
+#![deny(warnings)]
+
+use core::fmt;
+use std::{
+    collections::{hash_map::DefaultHasher, BTreeMap, BTreeSet},
+    env, fs,
+    hash::{Hash, Hasher},
+    path::{Path, PathBuf},
+    process::Command,
+    slice,
+    time::SystemTime,
+};
+
+use cargo_project::{Profile, Project};
+use failure::{ensure, Fail};
+use filetime::FileTime;
+use tempdir::TempDir;
+use xmas_elf::ElfFile;
+
+pub use crate::baseline::{Baseline, Change, ChangeKind};
+pub use crate::fields::{field_layouts, FieldLayout, FieldMismatch};
+use crate::fingerprint::Fingerprint;
+pub use crate::symbols::{shared_layout, verify, Iter, Layout, LayoutMismatch, Symbol, Symbols};
+
+mod abi;
+mod access;
+mod baseline;
+mod fields;
+mod fingerprint;
+mod symbols;
+
+/// The binary to build
+#[derive(Clone, Debug, PartialEq)]
+pub enum Artifact {
+    /// A binary of the package (`--bin`)
+    Bin(String),
+    /// An example of the package (`--example`)
+    Example(String),
+}
+
+/// The Cargo features to activate
+#[derive(Clone, Debug, PartialEq)]
+pub enum Features {
+    /// The default features
+    Default,
+    /// A space-separated list of features (`--features`)
+    List(String),
+    /// All the available features (`--all-features`)
+    All,
+}
+
+/// Compilation settings of a single core
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Core {
+    /// Compilation target; `None` means the target Cargo picks by default
+    pub target: Option<String>,
+    /// Extra flags passed to `rustc` when compiling the image and the data object of this core
+    pub rustflags: Vec<String>,
+}
+
+/// Build configuration
+#[derive(Clone, Debug)]
+pub struct Config {
+    /// The binary to build
+    pub artifact: Artifact,
+    /// The cores to build the binary for; the index of a core is its position in this list
+    pub cores: Vec<Core>,
+    /// Build in release mode
+    pub release: bool,
+    /// The Cargo features to activate
+    pub features: Features,
+    /// Print the commands that are executed
+    pub verbose: bool,
+}
+
+impl Config {
+    /// Configuration that builds `artifact` for two cores using the default target
+    pub fn new(artifact: Artifact) -> Self {
+        Config {
+            artifact,
+            cores: vec![Core::default(), Core::default()],
+            release: false,
+            features: Features::Default,
+            verbose: false,
+        }
+    }
+}
+
+/// The outcome of [`Pipeline::build`]
+#[derive(Debug)]
+pub struct Build {
+    /// One image per core, in core order
+    pub images: Vec<Image>,
+    /// The verified layout of the shared sections
+    pub layout: Layout,
+    /// The hash of `layout` that was embedded in the images; see [`Layout::abi_hash`]
+    pub abi_hash: u32,
+}
+
+/// A linked image
+#[derive(Debug)]
+pub struct Image {
+    /// The core this image is for
+    pub core: usize,
+    /// Path to the image
+    pub path: PathBuf,
+    /// `true` if the image was up to date and was not linked again
+    pub fresh: bool,
+}
+
+/// A command the pipeline executed didn't exit successfully
+///
+/// The command's own output has already been printed to the standard error.
+#[derive(Debug)]
+pub struct CommandFailed {
+    /// The command that failed
+    pub command: String,
+    /// The exit code of the command, if it exited normally
+    pub code: Option<i32>,
+}
+
+impl fmt::Display for CommandFailed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} didn't exit successfully", self.command)
+    }
+}
+
+impl Fail for CommandFailed {}
+
+/// The build pipeline of a μAMP application
+pub struct Pipeline {
+    config: Config,
+    host: String,
+    project: Project,
+    /// The output of `rustc -vV`
+    rustc: String,
+}
+
+impl Pipeline {
+    /// Prepares to build the Cargo project located at `dir`
+    pub fn new(config: Config, dir: impl AsRef<Path>) -> Result<Self, failure::Error> {
+        ensure!(!config.cores.is_empty(), "at least one core must be built");
+
+        let rustc = env::var_os("RUSTC").unwrap_or_else(|| "rustc".into());
+        let output = Command::new(rustc).arg("-vV").output()?;
+        ensure!(output.status.success(), "`rustc -vV` failed");
+        let rustc = String::from_utf8(output.stdout)?;
+        let meta = rustc_version::version_meta_for(&rustc)?;
+
+        Ok(Pipeline {
+            config,
+            host: meta.host,
+            project: Project::query(dir)?,
+            rustc,
+        })
+    }
+
+    /// The configuration of this pipeline
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    /// Compile checks the application, without linking it, for all the cores
+    pub fn check(&self) -> Result<(), failure::Error> {
+        self.touch()?;
+
+        // (target, rustflags) of the data passes that have been checked
+        let mut checked = BTreeSet::new();
+        for (i, core) in self.config.cores.iter().enumerate() {
+            // data
+            if checked.insert((&core.target, &core.rustflags)) {
+                let mut c = self.cargo(core);
+                c.args([
+                    "--",
+                    "--cfg",
+                    "microamp",
+                    "-C",
+                    "linker=microamp-true",
+                    "-A",
+                    "warnings",
+                ]);
+                c.args(self.cfgs());
+                c.args(&core.rustflags);
+                self.run(&mut c)?;
+            }
+
+            // code
+            let mut c = self.cargo(core);
+            c.args(["--", "--cfg"]);
+            c.arg(format!("core=\"{}\"", i));
+            c.args(["-C", "linker=microamp-true"]);
+            c.args(self.cfgs());
+            c.args(&core.rustflags);
+            self.run(&mut c)?;
+        }
+
+        Ok(())
+    }
+
+    /// Builds, and verifies, one image per core
+    ///
+    /// Images that are up to date are not linked again
+    pub fn build(&self) -> Result<Build, failure::Error> {
+        // the fingerprint of the last build of each image, if the image is still up to date
+        let mut fingerprints = vec![];
+        for i in 0..self.config.cores.len() {
+            let settings = self.settings(i)?;
+            let fresh = if self.image_path(i)?.exists() {
+                Fingerprint::load(&self.fingerprint_path(i)?).filter(|fp| fp.is_fresh(settings))
+            } else {
+                None
+            };
+
+            fingerprints.push((settings, fresh));
+        }
+
+        let mut images = vec![];
+        if fingerprints.iter().all(|(_, fresh)| fresh.is_some()) {
+            for i in 0..self.config.cores.len() {
+                images.push(Image {
+                    core: i,
+                    path: self.image_path(i)?,
+                    fresh: true,
+                });
+            }
+        } else {
+            self.touch()?;
+
+            // all the stripped data objects live in this directory
+            let td = TempDir::new("cargo-microamp")?;
+            // (target, rustflags) -> (stripped data object, hash of its contents)
+            let mut objects = BTreeMap::<_, (PathBuf, u64)>::new();
+            // (target, rustflags) -> (layout records, access records) of the data object
+            let mut records = BTreeMap::new();
+            for (i, core) in self.config.cores.iter().enumerate() {
+                if fingerprints[i].1.is_some() {
+                    continue;
+                }
+
+                // cores that are compiled with the same settings can use the same data object
+                let key = (&core.target, &core.rustflags);
+                if let Some((obj, _)) = objects.get(&key) {
+                    if self.config.verbose {
+                        eprintln!("reusing {} for core {}", obj.display(), i);
+                    }
+
+                    continue;
+                }
+
+                let so = self.data_pass(i)?;
+                records.insert(key, (fields::records(&so)?, access::records(&so)?));
+                // NOTE the linker scripts refer to the object by its file name so each data
+                // object gets its own directory
+                let dir = td.path().join(objects.len().to_string());
+                fs::create_dir(&dir)?;
+                let obj = dir.join("microamp-data.o");
+                strip(&so, &obj, self.config.verbose)?;
+
+                let data = fingerprint::hash_files(slice::from_ref(&obj))?;
+                objects.insert(key, (obj, data));
+            }
+
+            // the up to date images were linked with a data object that was not rebuilt; their
+            // records were stored with their fingerprints
+            for (i, core) in self.config.cores.iter().enumerate() {
+                if let Some(fp) = &fingerprints[i].1 {
+                    records
+                        .entry((&core.target, &core.rustflags))
+                        .or_insert_with(|| (fp.layouts.clone(), fp.accesses.clone()));
+                }
+            }
+
+            // (core, layouts of the shared variables), one per data object
+            let mut layouts = vec![];
+            // (target, rustflags) -> how the shared variables are accessed
+            let mut accesses = BTreeMap::new();
+            for (i, core) in self.config.cores.iter().enumerate() {
+                let key = (&core.target, &core.rustflags);
+                if accesses.contains_key(&key) {
+                    continue;
+                }
+
+                let (layout, access) = &records[&key];
+                layouts.push((i, fields::parse_records(layout)?));
+                accesses.insert(key, access::parse_records(access)?);
+            }
+
+            // with different targets the same type may have a different layout on each core
+            fields::compare(&layouts)?;
+            // and the cores may not be able to operate on the same data
+            let targets = self
+                .config
+                .cores
+                .iter()
+                .map(|core| {
+                    let target = core.target.as_deref().unwrap_or("default target");
+                    (target, &accesses[&(&core.target, &core.rustflags)])
+                })
+                .collect::<Vec<_>>();
+            access::check(&targets)?;
+
+            for (i, core) in self.config.cores.iter().enumerate() {
+                let (settings, fresh) = &fingerprints[i];
+                let key = (&core.target, &core.rustflags);
+
+                // an image whose inputs didn't change is still stale if its data object changed
+                let (obj, data) = match (fresh, objects.get(&key)) {
+                    (Some(fp), Some((obj, data))) if fp.data != *data => (obj, *data),
+                    (Some(_), _) => {
+                        images.push(Image {
+                            core: i,
+                            path: self.image_path(i)?,
+                            fresh: true,
+                        });
+                        continue;
+                    }
+                    (None, Some((obj, data))) => (obj, *data),
+                    (None, None) => unreachable!(),
+                };
+
+                let path = self.link(i, obj)?;
+
+                // NOTE without the compiler's dep-info we can't tell when this image becomes
+                // stale so it will be linked again on the next invocation
+                let dep_info = self.artifact_path(i)?.with_extension("d");
+                let fingerprint = self.fingerprint_path(i)?;
+                if dep_info.exists() {
+                    let inputs = fingerprint::parse_dep_info(&dep_info)?;
+                    let (layouts, accesses) = records[&key].clone();
+                    let fp = Fingerprint {
+                        settings: *settings,
+                        data,
+                        sources: fingerprint::hash_files(&inputs)?,
+                        inputs,
+                        layouts,
+                        accesses,
+                    };
+                    fp.store(&fingerprint)?;
+                } else {
+                    Fingerprint::remove(&fingerprint)?;
+                }
+
+                images.push(Image {
+                    core: i,
+                    path,
+                    fresh: false,
+                });
+            }
+        }
+
+        let paths = images
+            .iter()
+            .map(|image| image.path.clone())
+            .collect::<Vec<_>>();
+        let layout = verify(&paths)?;
+
+        // the cores compare these at runtime to detect that they were flashed with images from
+        // different builds
+        let abi_hash = layout.abi_hash();
+        for path in &paths {
+            abi::embed(path, abi_hash)?;
+        }
+
+        Ok(Build {
+            images,
+            layout,
+            abi_hash,
+        })
+    }
+
+    /// Compiles the application with `--cfg microamp` for `core`
+    ///
+    /// Returns the path to the resulting object file; this object file still needs to be
+    /// [`strip`]-ped before it's passed to the linker
+    pub fn data_pass(&self, core: usize) -> Result<PathBuf, failure::Error> {
+        let settings = &self.config.cores[core];
+
+        // NOTE `--cfg microamp` only reaches the crate being built so all the `#[shared]`
+        // variables are defined in it; a single codegen unit is enough to get all of them
+        // into one object file -- there's no need for whole-program LTO
+        let mut c = self.cargo(settings);
+        c.args([
+            "--",
+            "-C",
+            "codegen-units=1",
+            "--cfg",
+            "microamp",
+            "--emit=obj",
+            "-A",
+            "warnings",
+            "-C",
+            "linker=microamp-true",
+        ]);
+        c.args(self.cfgs());
+        c.args(&settings.rustflags);
+        self.run(&mut c)?;
+
+        let path = self.artifact_path(core)?;
+        let parent = path.parent().expect("unreachable");
+        let (haystack, name) = match &self.config.artifact {
+            Artifact::Bin(bin) => (parent.join("deps"), bin),
+            Artifact::Example(ex) => (parent.to_owned(), ex),
+        };
+
+        let prefix = format!("{}-", name.replace('-', "_"));
+        let mut so = None;
+        // most recently modified
+        let mut mrm = SystemTime::UNIX_EPOCH;
+        for e in fs::read_dir(haystack)? {
+            let e = e?;
+            let p = e.path();
+
+            if p.extension().map(|ext| ext == "o").unwrap_or(false)
+                && p.file_stem()
+                    .expect("unreachable")
+                    .to_str()
+                    .expect("unreachable")
+                    .starts_with(&prefix)
+            {
+                let modified = e.metadata()?.modified()?;
+                if so.is_none() || modified > mrm {
+                    so = Some(p);
+                    mrm = modified;
+                }
+            }
+        }
+
+        Ok(so.expect("UNREACHABLE"))
+    }
+
+    /// Compiles and links the image of `core` against the stripped data object `data`
+    ///
+    /// Returns the path to the image
+    pub fn link(&self, core: usize, data: &Path) -> Result<PathBuf, failure::Error> {
+        let settings = &self.config.cores[core];
+
+        let mut c = self.cargo(settings);
+        c.args([
+            "--",
+            "--cfg",
+            &format!("core=\"{}\"", core),
+            "-C",
+            &format!("link-arg=-Tcore{}.x", core),
+            "-C",
+            &format!("link-arg={}", data.display()),
+        ]);
+        c.args(self.cfgs());
+        c.args(&settings.rustflags);
+        self.run(&mut c)?;
+
+        let dst = self.image_path(core)?;
+        fs::rename(self.artifact_path(core)?, &dst)?;
+
+        Ok(dst)
+    }
+
+    /// Path to the image of `core`
+    pub fn image_path(&self, core: usize) -> Result<PathBuf, failure::Error> {
+        let path = self.artifact_path(core)?;
+        let filename = format!(
+            "{}-{}",
+            path.file_name()
+                .expect("unreachable")
+                .to_str()
+                .expect("unreachable"),
+            core
+        );
+
+        Ok(path.with_file_name(filename))
+    }
+
+    /// Path to the artifact Cargo produces when compiling for `core`
+    fn artifact_path(&self, core: usize) -> Result<PathBuf, failure::Error> {
+        let artifact = match &self.config.artifact {
+            Artifact::Bin(bin) => cargo_project::Artifact::Bin(bin),
+            Artifact::Example(ex) => cargo_project::Artifact::Example(ex),
+        };
+        let profile = if self.config.release {
+            Profile::Release
+        } else {
+            Profile::Dev
+        };
+
+        self.project.path(
+            artifact,
+            profile,
+            self.config.cores[core].target.as_ref().map(|s| &s[..]),
+            &self.host,
+        )
+    }
+
+    fn fingerprint_path(&self, core: usize) -> Result<PathBuf, failure::Error> {
+        let image = self.image_path(core)?;
+
+        Ok(image
+            .parent()
+            .expect("unreachable")
+            .join(".microamp")
+            .join(image.file_name().expect("unreachable")))
+    }
+
+    /// Hash of everything but the sources that affects the image of `core`
+    fn settings(&self, core: usize) -> Result<u64, failure::Error> {
+        let settings = &self.config.cores[core];
+        let parent = self.artifact_path(core)?;
+        let parent = parent.parent().expect("unreachable");
+
+        let mut hasher = DefaultHasher::new();
+        env!("CARGO_PKG_VERSION").hash(&mut hasher);
+        // switching toolchains changes the output of the compiler
+        self.rustc.hash(&mut hasher);
+        settings.target.hash(&mut hasher);
+        settings.rustflags.hash(&mut hasher);
+        self.heterogeneous().hash(&mut hasher);
+        self.config.release.hash(&mut hasher);
+        match &self.config.features {
+            Features::Default => 0.hash(&mut hasher),
+            Features::List(features) => (1, features).hash(&mut hasher),
+            Features::All => 2.hash(&mut hasher),
+        }
+        fingerprint::hash_linker_scripts(&[self.root(), &parent.join("build")], core, &mut hasher)?;
+        // the profiles, the dependencies and the flags in `.cargo/config` and in the environment
+        fingerprint::hash_cargo_files(self.root(), &mut hasher)?;
+        fingerprint::hash_env(&mut hasher);
+
+        Ok(hasher.finish())
+    }
+
+    /// Whether the cores are compiled for different targets
+    fn heterogeneous(&self) -> bool {
+        let targets = self
+            .config
+            .cores
+            .iter()
+            .map(|core| &core.target)
+            .collect::<BTreeSet<_>>();
+
+        targets.len() > 1
+    }
+
+    /// `--cfg` flags passed to all the compilations
+    ///
+    /// With different targets `#[shared]` variables must have a `Shareable` type
+    fn cfgs(&self) -> &'static [&'static str] {
+        if self.heterogeneous() {
+            &["--cfg", "microamp_heterogeneous"]
+        } else {
+            &[]
+        }
+    }
+
+    fn root(&self) -> &Path {
+        self.project.toml().parent().expect("UNREACHABLE")
+    }
+
+    /// "touch" some source file to trigger a rebuild
+    fn touch(&self) -> Result<(), failure::Error> {
+        let root = self.root();
+        let now = FileTime::from_system_time(SystemTime::now());
+        match &self.config.artifact {
+            Artifact::Bin(bin) => {
+                if bin == self.project.name() {
+                    filetime::set_file_times(root.join("src/main.rs"), now, now)?
+                } else {
+                    filetime::set_file_times(root.join(format!("src/bin/{}.rs", bin)), now, now)?
+                }
+            }
+
+            Artifact::Example(ex) => {
+                filetime::set_file_times(root.join(format!("examples/{}.rs", ex)), now, now)?
+            }
+        }
+
+        Ok(())
+    }
+
+    fn cargo(&self, core: &Core) -> Command {
+        let mut c = Command::new("cargo");
+        c.arg("rustc");
+        match &self.config.artifact {
+            Artifact::Bin(bin) => {
+                c.args(["--bin", bin]);
+            }
+            Artifact::Example(ex) => {
+                c.args(["--example", ex]);
+            }
+        }
+        if let Some(target) = &core.target {
+            c.args(["--target", target]);
+        }
+        if self.config.release {
+            c.arg("--release");
+        }
+        match &self.config.features {
+            Features::Default => {}
+            Features::List(features) => {
+                c.args(["--features", features]);
+            }
+            Features::All => {
+                c.arg("--all-features");
+            }
+        }
+        c
+    }
+
+    fn run(&self, c: &mut Command) -> Result<(), failure::Error> {
+        run(c, self.config.verbose)
+    }
+}
+
+/// Copies the data object `object` to `output` and strips everything but the shared sections
+/// (`.shared` and `.shared.*`) from the copy
+pub fn strip(object: &Path, output: &Path, verbose: bool) -> Result<(), failure::Error> {
+    fs::copy(object, output)?;
+
+    // FIXME use a Rust library instead of shelling out to `strip`
+    let mut c = Command::new("arm-none-eabi-strip");
+    c.args([
+        "-R",
+        "*",
+        "-R",
+        "!.shared",
+        "-R",
+        "!.shared.*",
+        "--strip-unneeded",
+    ])
+    .arg(output);
+    run(&mut c, verbose)
+}
+
+/// Returns the contents of all the sections named `name` of the object file at `path`, in order
+///
+/// Each `#[shared]` variable may put its records in a section of its own
+fn section_data(path: &Path, name: &str) -> Result<Vec<u8>, failure::Error> {
+    let contents = fs::read(path)?;
+    let elf = ElfFile::new(&contents).map_err(failure::err_msg)?;
+
+    let mut data = vec![];
+    for i in 1..elf.header.pt2.sh_count() {
+        let sh = elf.section_header(i).map_err(failure::err_msg)?;
+        if sh.get_name(&elf) == Ok(name) {
+            data.extend_from_slice(sh.raw_data(&elf));
+        }
+    }
+
+    Ok(data)
+}
+
+fn run(c: &mut Command, verbose: bool) -> Result<(), failure::Error> {
+    if verbose {
+        eprintln!("{:?}", c);
+    }
+
+    let status = c.status()?;
+    if status.success() {
+        Ok(())
+    } else {
+        Err(CommandFailed {
+            command: format!("{:?}", c),
+            code: status.code(),
+        }
+        .into())
+    }
+}
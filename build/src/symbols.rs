@@ -0,0 +1,845 @@
+use core::{convert::TryInto, fmt, iter::FromIterator};
+use std::{
+    collections::{btree_map, BTreeMap, BTreeSet},
+    fs,
+    path::{Path, PathBuf},
+};
+
+use failure::{bail, ensure, Fail};
+use xmas_elf::{
+    header,
+    sections::{SectionData, ShType},
+    symbol_table::Entry,
+    ElfFile,
+};
+
+/// The symbols that live in a shared section of an image, indexed by their offset from the start
+/// of the section
+///
+/// Offsets, unlike addresses, are the same on cores that map the shared memory at different
+/// addresses
+#[derive(Clone, PartialEq)]
+pub struct Symbols {
+    entries: BTreeMap<u64, Symbol>,
+}
+
+impl Symbols {
+    /// Returns the symbol located at `offset`
+    pub fn get(&self, offset: u64) -> Option<&Symbol> {
+        self.entries.get(&offset)
+    }
+
+    /// Iterates over the symbols in offset order
+    pub fn iter(&self) -> Iter<'_> {
+        Iter {
+            inner: self.entries.iter(),
+        }
+    }
+
+    /// Returns the number of symbols
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Returns `true` if there are no symbols
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+/// Iterator over `(offset, symbol)` pairs
+pub struct Iter<'a> {
+    inner: btree_map::Iter<'a, u64, Symbol>,
+}
+
+impl<'a> Iterator for Iter<'a> {
+    type Item = (u64, &'a Symbol);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.inner.next().map(|(offset, symbol)| (*offset, symbol))
+    }
+}
+
+impl<'a> IntoIterator for &'a Symbols {
+    type Item = (u64, &'a Symbol);
+    type IntoIter = Iter<'a>;
+
+    fn into_iter(self) -> Iter<'a> {
+        self.iter()
+    }
+}
+
+/// Builds a layout from `(offset, (size, align, name))` entries
+impl FromIterator<(u64, (u64, u64, Option<String>))> for Symbols {
+    fn from_iter<I>(iter: I) -> Self
+    where
+        I: IntoIterator<Item = (u64, (u64, u64, Option<String>))>,
+    {
+        Symbols {
+            entries: iter
+                .into_iter()
+                .map(|(offset, (size, align, name))| (offset, Symbol { size, align, name }))
+                .collect(),
+        }
+    }
+}
+
+impl fmt::Debug for Symbols {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        struct Hex(u64);
+
+        impl fmt::Debug for Hex {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                write!(f, "{:#08x}", self.0)
+            }
+        }
+
+        let mut s = f.debug_map();
+        for (offset, symbol) in &self.entries {
+            s.entry(&Hex(*offset), symbol);
+        }
+
+        s.finish()
+    }
+}
+
+/// The symbols of all the shared sections (`.shared` and `.shared.*`) of an image, indexed by
+/// section name
+#[derive(Clone, PartialEq)]
+pub struct Layout {
+    sections: BTreeMap<String, Symbols>,
+    /// section name -> start address; informational, the images may place the sections at
+    /// different addresses
+    addresses: BTreeMap<String, u64>,
+    /// symbol -> fingerprint of the type of the variable, as seen by the data object
+    fingerprints: BTreeMap<String, u64>,
+}
+
+impl Layout {
+    /// A hash of the layout: the offset, size, alignment and name of every symbol of every
+    /// shared section, and the fingerprints of the types of the shared variables. The start
+    /// addresses of the sections are not part of it
+    ///
+    /// This is the value `cargo microamp` embeds in the images; it's never 0
+    pub fn abi_hash(&self) -> u32 {
+        let mut hash = Fnv::new();
+        for (name, symbols) in &self.sections {
+            hash.write(name.as_bytes());
+            hash.write_u64(symbols.len() as u64);
+            for (offset, symbol) in symbols {
+                hash.write_u64(offset);
+                hash.write_u64(symbol.size);
+                hash.write_u64(symbol.align);
+                hash.write(symbol.name().unwrap_or("").as_bytes());
+            }
+        }
+
+        for (symbol, fingerprint) in &self.fingerprints {
+            hash.write(symbol.as_bytes());
+            hash.write_u64(*fingerprint);
+        }
+
+        // 0 means "not embedded"
+        hash.finish().max(1)
+    }
+
+    /// Returns the fingerprint of the type of the shared variable `symbol`
+    ///
+    /// The fingerprint is a hash of the name, size, alignment and fields of the type
+    pub fn fingerprint(&self, symbol: &str) -> Option<u64> {
+        self.fingerprints.get(symbol).cloned()
+    }
+
+    /// Returns the symbols of the shared section `name`
+    pub fn section(&self, name: &str) -> Option<&Symbols> {
+        self.sections.get(name)
+    }
+
+    /// Returns the start address of the shared section `name`
+    pub fn address(&self, name: &str) -> Option<u64> {
+        self.addresses.get(name).cloned()
+    }
+
+    /// Iterates over the shared sections in name order
+    pub fn sections(&self) -> btree_map::Iter<'_, String, Symbols> {
+        self.sections.iter()
+    }
+}
+
+impl fmt::Debug for Layout {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_map().entries(&self.sections).finish()
+    }
+}
+
+/// A symbol in a shared section
+#[derive(Clone, PartialEq)]
+pub struct Symbol {
+    size: u64,
+    align: u64,
+    name: Option<String>,
+}
+
+impl Symbol {
+    /// The size of the symbol in bytes
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    /// The alignment of the symbol's address, up to the alignment of its section
+    pub fn align(&self) -> u64 {
+        self.align
+    }
+
+    /// The name of the symbol, if it could be read from the symbol table
+    pub fn name(&self) -> Option<&str> {
+        self.name.as_ref().map(|s| &s[..])
+    }
+}
+
+impl fmt::Debug for Symbol {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut s = f.debug_struct("Symbol");
+        s.field("size", &self.size);
+        s.field("align", &self.align);
+        if let Some(name) = &self.name {
+            s.field("name", name);
+        } else {
+            s.field("name", &"?");
+        }
+        s.finish()
+    }
+}
+
+/// The layout of a shared section differs between two images
+#[derive(Debug)]
+pub struct LayoutMismatch {
+    /// The name of the section
+    pub section: String,
+    /// The image the others are compared against, and the layout of the section in it
+    pub expected: (PathBuf, Symbols),
+    /// The image whose layout doesn't match, and the layout of the section in it
+    pub found: (PathBuf, Symbols),
+}
+
+impl fmt::Display for LayoutMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "the layout of the `{}` section doesn't match\n\
+             {}:\n{:#?}\n{}\n{:#?}",
+            self.section,
+            filename(&self.expected.0),
+            self.expected.1,
+            filename(&self.found.0),
+            self.found.1,
+        )
+    }
+}
+
+impl Fail for LayoutMismatch {}
+
+/// Collects the symbols that live in the shared sections of the image at `path`
+pub fn shared_layout(path: &Path) -> Result<Layout, failure::Error> {
+    let contents = fs::read(path)?;
+    let elf = ElfFile::new(&contents).map_err(failure::err_msg)?;
+
+    let executable = elf.header.pt2.type_().as_type() == header::Type::Executable;
+
+    // section index -> (section name, section alignment, section address)
+    let mut shared = BTreeMap::new();
+    for i in 1..elf.header.pt2.sh_count() {
+        if let Ok(sh) = elf.section_header(i) {
+            if let Ok(name) = sh.get_name(&elf) {
+                if is_shared(name) {
+                    // the contents of these sections are not part of the image; they are cleared
+                    // at boot, or never initialized
+                    ensure!(
+                        !executable
+                            || !(name.ends_with(".bss") || name.ends_with(".uninit"))
+                            || sh.get_type() == Ok(ShType::NoBits),
+                        "({}) the `{}` section is part of the load image; \
+                         mark it as `(NOLOAD)` in the linker script",
+                        filename(path),
+                        name
+                    );
+
+                    shared.insert(i, (name, section_align(&contents, &elf, i), sh.address()));
+                }
+            }
+        }
+    }
+
+    ensure!(
+        !shared.is_empty(),
+        "({}) `.shared` section is missing",
+        filename(path)
+    );
+
+    let mut sections = shared
+        .values()
+        .map(|(name, _, _)| {
+            (
+                String::from(*name),
+                Symbols {
+                    entries: BTreeMap::new(),
+                },
+            )
+        })
+        .collect::<BTreeMap<_, _>>();
+
+    let addresses = shared
+        .values()
+        .map(|(name, _, address)| (String::from(*name), *address))
+        .collect::<BTreeMap<_, _>>();
+
+    let symtab = if let Some(symtab) = elf.find_section_by_name(".symtab") {
+        symtab
+    } else {
+        return Ok(Layout {
+            sections,
+            addresses,
+            fingerprints: BTreeMap::new(),
+        });
+    };
+
+    // symbol name -> (section name, offset from the start of the section, size)
+    let mut placed = BTreeMap::new();
+    // symbol name -> (pinned offset, expected size)
+    let mut pins = BTreeMap::<String, (Option<u64>, Option<u64>)>::new();
+    // symbol name -> fingerprint of its type, as seen by the data object and by the image
+    let mut fingerprints = BTreeMap::new();
+    let mut own_fingerprints = BTreeMap::new();
+    match symtab.get_data(&elf).map_err(failure::err_msg)? {
+        SectionData::SymbolTable32(entries) => {
+            for entry in entries {
+                let size = entry.size();
+                if let Some((section, section_align, start)) = shared.get(&entry.shndx()) {
+                    if size != 0 {
+                        let address = entry.value();
+                        let offset = address - start;
+                        let name = entry.get_name(&elf).ok().map(String::from);
+                        if let Some(name) = &name {
+                            placed.insert(name.clone(), (*section, offset, size));
+                        }
+
+                        sections
+                            .get_mut(*section)
+                            .expect("unreachable")
+                            .entries
+                            .insert(
+                                offset,
+                                Symbol {
+                                    size,
+                                    align: align_of(address, *section_align),
+                                    name,
+                                },
+                            );
+                    }
+                } else if entry.shndx() == SHN_ABS {
+                    // the pins emitted by `#[shared(offset = .., size = ..)]`
+                    if let Ok(name) = entry.get_name(&elf) {
+                        if let Some(symbol) = name.strip_prefix(PIN_OFFSET) {
+                            pins.entry(symbol.to_owned()).or_default().0 = Some(entry.value());
+                        } else if let Some(symbol) = name.strip_prefix(PIN_SIZE) {
+                            pins.entry(symbol.to_owned()).or_default().1 = Some(entry.value());
+                        } else if let Some(symbol) = name.strip_prefix(FINGERPRINT_DATA) {
+                            fingerprints.insert(symbol.to_owned(), entry.value());
+                        } else if let Some(symbol) = name.strip_prefix(FINGERPRINT_IMAGE) {
+                            own_fingerprints.insert(symbol.to_owned(), entry.value());
+                        }
+                    }
+                }
+            }
+        }
+
+        SectionData::SymbolTable64(_) => bail!("64-bit ELF files are not currently supported"),
+
+        _ => bail!("malformed .symtab section"),
+    }
+
+    // the addresses in object files are not final; pins can only be checked on linked images
+    if executable {
+        for (symbol, (offset, size)) in pins {
+            let (section, actual_offset, actual_size) = match placed.get(&symbol) {
+                Some(placement) => *placement,
+                None => bail!(
+                    "({}) `{}` is pinned but is not in a shared section",
+                    filename(path),
+                    symbol
+                ),
+            };
+
+            if let Some(offset) = offset {
+                ensure!(
+                    offset == actual_offset,
+                    "({}) `{}` is pinned at offset {:#x} of `{}` but is at offset {:#x}",
+                    filename(path),
+                    symbol,
+                    offset,
+                    section,
+                    actual_offset
+                );
+            }
+
+            if let Some(size) = size {
+                ensure!(
+                    size == actual_size,
+                    "({}) `{}` is expected to be {} bytes in size but is {} bytes",
+                    filename(path),
+                    symbol,
+                    size,
+                    actual_size
+                );
+            }
+        }
+    }
+
+    // the image was compiled with `--cfg core`, the data object without it; both must have seen
+    // the same definition of the type
+    for (symbol, own) in own_fingerprints {
+        if let Some(data) = fingerprints.get(&symbol) {
+            ensure!(
+                own == *data,
+                "({}) the type of `{}` has a different definition in this image than in the \
+                 shared data (fingerprint {:#010x} vs {:#010x})",
+                filename(path),
+                symbol,
+                own,
+                data
+            );
+        }
+    }
+
+    Ok(Layout {
+        sections,
+        addresses,
+        fingerprints,
+    })
+}
+
+/// Checks that all the shared sections have the same layout on all the `images`
+///
+/// The layouts are compared offset by offset, so the images may place the sections at different
+/// addresses. Returns the verified layout, with the addresses of the first image. If the layouts
+/// differ the error is a [`LayoutMismatch`]
+pub fn verify(images: &[PathBuf]) -> Result<Layout, failure::Error> {
+    let mut base: Option<(&PathBuf, Layout)> = None;
+    for image in images {
+        let layout = shared_layout(image)?;
+
+        if let Some((base_image, base_layout)) = &base {
+            let empty = Symbols {
+                entries: BTreeMap::new(),
+            };
+            let names = base_layout
+                .sections
+                .keys()
+                .chain(layout.sections.keys())
+                .collect::<BTreeSet<_>>();
+            for name in names {
+                let expected = base_layout.sections.get(name).unwrap_or(&empty);
+                let found = layout.sections.get(name).unwrap_or(&empty);
+
+                if expected != found {
+                    return Err(LayoutMismatch {
+                        section: name.clone(),
+                        expected: ((*base_image).clone(), expected.clone()),
+                        found: (image.clone(), found.clone()),
+                    }
+                    .into());
+                }
+            }
+
+            for (symbol, expected) in &base_layout.fingerprints {
+                if let Some(found) = layout.fingerprints.get(symbol) {
+                    ensure!(
+                        expected == found,
+                        "the type of `{}` has a different definition in {} (fingerprint {:#010x}) \
+                         than in {} (fingerprint {:#010x})",
+                        symbol,
+                        filename(image),
+                        found,
+                        filename(base_image),
+                        expected
+                    );
+                }
+            }
+        } else {
+            base = Some((image, layout));
+        }
+    }
+
+    Ok(base.map(|(_, layout)| layout).unwrap_or(Layout {
+        sections: BTreeMap::new(),
+        addresses: BTreeMap::new(),
+        fingerprints: BTreeMap::new(),
+    }))
+}
+
+/// 32-bit FNV-1a; the hash has to be the same on every host so `std`'s hashers can't be used
+struct Fnv(u32);
+
+impl Fnv {
+    fn new() -> Self {
+        Fnv(0x811c_9dc5)
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        self.write_u64(bytes.len() as u64);
+        for byte in bytes {
+            self.0 ^= u32::from(*byte);
+            self.0 = self.0.wrapping_mul(0x0100_0193);
+        }
+    }
+
+    fn write_u64(&mut self, value: u64) {
+        for byte in &value.to_le_bytes() {
+            self.0 ^= u32::from(*byte);
+            self.0 = self.0.wrapping_mul(0x0100_0193);
+        }
+    }
+
+    fn finish(&self) -> u32 {
+        self.0
+    }
+}
+
+/// Section index of absolute symbols
+const SHN_ABS: u16 = 0xfff1;
+
+/// Prefix of the absolute symbols that hold the offset a shared variable is pinned at
+const PIN_OFFSET: &str = "microamp.offset.";
+
+/// Prefix of the absolute symbols that hold the expected size of a pinned shared variable
+const PIN_SIZE: &str = "microamp.size.";
+
+/// Prefix of the absolute symbols that hold the fingerprint of the type of a shared variable, as
+/// computed by the data pass
+const FINGERPRINT_DATA: &str = "microamp.fingerprint.data.";
+
+/// Prefix of the absolute symbols that hold the fingerprint of the type of a shared variable, as
+/// computed by the compilation of an image
+const FINGERPRINT_IMAGE: &str = "microamp.fingerprint.image.";
+
+/// The alignment (`sh_addralign`) of the section at `index`, which `xmas-elf` doesn't expose
+fn section_align(contents: &[u8], elf: &ElfFile<'_>, index: u16) -> u64 {
+    // offset of `sh_addralign` in `Elf32_Shdr`
+    const SH_ADDRALIGN: usize = 32;
+
+    if elf.header.pt1.class() != header::Class::ThirtyTwo {
+        return 1;
+    }
+
+    let start = elf.header.pt2.sh_offset() as usize
+        + usize::from(index) * usize::from(elf.header.pt2.sh_entry_size())
+        + SH_ADDRALIGN;
+    let bytes = match contents.get(start..start + 4) {
+        Some(bytes) => bytes.try_into().expect("unreachable"),
+        None => return 1,
+    };
+
+    u64::from(match elf.header.pt1.data() {
+        header::Data::BigEndian => u32::from_be_bytes(bytes),
+        _ => u32::from_le_bytes(bytes),
+    })
+}
+
+/// The largest power of two that divides `address`, up to `max`
+fn align_of(address: u64, max: u64) -> u64 {
+    let max = max.max(1);
+    if address == 0 {
+        max
+    } else {
+        (1 << address.trailing_zeros()).min(max)
+    }
+}
+
+/// Whether `name` is the name of a shared section
+fn is_shared(name: &str) -> bool {
+    name == ".shared" || name.starts_with(".shared.")
+}
+
+fn filename(path: &Path) -> String {
+    path.file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_else(|| path.display().to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, path::PathBuf};
+
+    use tempdir::TempDir;
+
+    use super::{shared_layout, verify, LayoutMismatch, SHN_ABS};
+
+    /// A variable in the `.shared` section: name, offset and size
+    type Variable<'a> = (&'a str, u32, u32);
+
+    /// Writes a minimal 32-bit ARM executable whose `.shared` section starts at `address` and
+    /// contains `variables`, plus the absolute symbols `absolutes`
+    fn image(
+        td: &TempDir,
+        name: &str,
+        address: u32,
+        variables: &[Variable<'_>],
+        absolutes: &[(&str, u32)],
+    ) -> PathBuf {
+        fn u16(bytes: &mut Vec<u8>, value: u16) {
+            bytes.extend_from_slice(&value.to_le_bytes());
+        }
+
+        fn u32(bytes: &mut Vec<u8>, value: u32) {
+            bytes.extend_from_slice(&value.to_le_bytes());
+        }
+
+        const EHSIZE: u32 = 52;
+        const SHENTSIZE: u32 = 40;
+        const SYMENTSIZE: u32 = 16;
+
+        let size = variables
+            .iter()
+            .map(|(_, offset, size)| offset + size)
+            .max()
+            .unwrap_or(0);
+
+        // the first entry of the symbol and string tables is the null one
+        let mut strtab = vec![0];
+        let mut symtab = vec![0; SYMENTSIZE as usize];
+        let symbols = variables
+            .iter()
+            .map(|(name, offset, size)| (*name, address + offset, *size, 1))
+            .chain(
+                absolutes
+                    .iter()
+                    .map(|(name, value)| (*name, *value, 0, SHN_ABS)),
+            );
+        for (name, value, size, shndx) in symbols {
+            u32(&mut symtab, strtab.len() as u32);
+            strtab.extend_from_slice(name.as_bytes());
+            strtab.push(0);
+            u32(&mut symtab, value);
+            u32(&mut symtab, size);
+            // STB_GLOBAL, STT_OBJECT
+            symtab.push(0x11);
+            symtab.push(0);
+            u16(&mut symtab, shndx);
+        }
+
+        let shstrtab = b"\0.shared\0.symtab\0.strtab\0.shstrtab\0";
+
+        // `xmas-elf` reads the tables in place so they must be 4-byte aligned
+        let pad = |len: u32| len.div_ceil(4) * 4;
+
+        // the ELF header, the contents of the sections and then the section headers
+        let shared_offset = EHSIZE;
+        let symtab_offset = pad(shared_offset + size);
+        let strtab_offset = symtab_offset + symtab.len() as u32;
+        let shstrtab_offset = strtab_offset + strtab.len() as u32;
+        let shoff = pad(shstrtab_offset + shstrtab.len() as u32);
+
+        let mut bytes = vec![0x7f, b'E', b'L', b'F', 1, 1, 1];
+        bytes.resize(16, 0);
+        u16(&mut bytes, 2); // ET_EXEC
+        u16(&mut bytes, 40); // EM_ARM
+        u32(&mut bytes, 1);
+        u32(&mut bytes, 0); // e_entry
+        u32(&mut bytes, 0); // e_phoff
+        u32(&mut bytes, shoff);
+        u32(&mut bytes, 0); // e_flags
+        u16(&mut bytes, EHSIZE as u16);
+        u16(&mut bytes, 32);
+        u16(&mut bytes, 0); // e_phnum
+        u16(&mut bytes, SHENTSIZE as u16);
+        u16(&mut bytes, 5); // e_shnum
+        u16(&mut bytes, 4); // e_shstrndx
+
+        bytes.resize(symtab_offset as usize, 0);
+        bytes.extend_from_slice(&symtab);
+        bytes.extend_from_slice(&strtab);
+        bytes.extend_from_slice(shstrtab);
+        bytes.resize(shoff as usize, 0);
+
+        // name, type, flags, address, offset, size, link, info, alignment, entry size
+        let headers = [
+            [0; 10],
+            [1, 1, 3, address, shared_offset, size, 0, 0, 4, 0],
+            [
+                9,
+                2,
+                0,
+                0,
+                symtab_offset,
+                symtab.len() as u32,
+                3,
+                1,
+                4,
+                SYMENTSIZE,
+            ],
+            [17, 3, 0, 0, strtab_offset, strtab.len() as u32, 0, 0, 1, 0],
+            [
+                25,
+                3,
+                0,
+                0,
+                shstrtab_offset,
+                shstrtab.len() as u32,
+                0,
+                0,
+                1,
+                0,
+            ],
+        ];
+        for header in &headers {
+            for field in header {
+                u32(&mut bytes, *field);
+            }
+        }
+
+        let path = td.path().join(name);
+        fs::write(&path, bytes).unwrap();
+        path
+    }
+
+    #[test]
+    fn layout() {
+        let td = TempDir::new("microamp-symbols").unwrap();
+        let path = image(
+            &td,
+            "core0",
+            0x2000_0000,
+            &[("app::X", 0, 4), ("app::Y", 8, 2)],
+            &[],
+        );
+
+        let layout = shared_layout(&path).unwrap();
+        assert_eq!(layout.address(".shared"), Some(0x2000_0000));
+
+        let symbols = layout.section(".shared").unwrap();
+        assert_eq!(symbols.len(), 2);
+        let y = symbols.get(8).unwrap();
+        assert_eq!(y.name(), Some("app::Y"));
+        assert_eq!(y.size(), 2);
+        assert_eq!(y.align(), 4);
+    }
+
+    #[test]
+    fn pins() {
+        let td = TempDir::new("microamp-symbols").unwrap();
+        let path = image(
+            &td,
+            "core0",
+            0x2000_0000,
+            &[("app::X", 0, 4), ("app::Y", 0x40, 4)],
+            &[
+                ("microamp.offset.app::Y", 0x40),
+                ("microamp.size.app::Y", 4),
+            ],
+        );
+
+        assert!(shared_layout(&path).is_ok());
+    }
+
+    #[test]
+    fn misplaced_pin() {
+        let td = TempDir::new("microamp-symbols").unwrap();
+        let path = image(
+            &td,
+            "core0",
+            0x2000_0000,
+            &[("app::X", 0, 4), ("app::Y", 4, 4)],
+            &[("microamp.offset.app::Y", 0x40)],
+        );
+
+        let error = shared_layout(&path).unwrap_err().to_string();
+        assert_eq!(
+            error,
+            "(core0) `app::Y` is pinned at offset 0x40 of `.shared` but is at offset 0x4"
+        );
+    }
+
+    #[test]
+    fn missized_pin() {
+        let td = TempDir::new("microamp-symbols").unwrap();
+        let path = image(
+            &td,
+            "core0",
+            0x2000_0000,
+            &[("app::Y", 0x40, 4)],
+            &[
+                ("microamp.offset.app::Y", 0x40),
+                ("microamp.size.app::Y", 2),
+            ],
+        );
+
+        let error = shared_layout(&path).unwrap_err().to_string();
+        assert_eq!(
+            error,
+            "(core0) `app::Y` is expected to be 2 bytes in size but is 4 bytes"
+        );
+    }
+
+    #[test]
+    fn unplaced_pin() {
+        let td = TempDir::new("microamp-symbols").unwrap();
+        let path = image(
+            &td,
+            "core0",
+            0x2000_0000,
+            &[("app::X", 0, 4)],
+            &[("microamp.offset.app::Y", 0x40)],
+        );
+
+        let error = shared_layout(&path).unwrap_err().to_string();
+        assert_eq!(
+            error,
+            "(core0) `app::Y` is pinned but is not in a shared section"
+        );
+    }
+
+    #[test]
+    fn relocated_section() {
+        let td = TempDir::new("microamp-symbols").unwrap();
+        // core 1 sees the shared memory through a different bus, at a different address
+        let variables = [("app::X", 0, 4), ("app::Y", 8, 2)];
+        let images = [
+            image(&td, "core0", 0x2000_0000, &variables, &[]),
+            image(&td, "core1", 0x3000_0000, &variables, &[]),
+        ];
+
+        let layout = verify(&images).unwrap();
+        assert_eq!(layout.address(".shared"), Some(0x2000_0000));
+        assert_eq!(
+            layout.abi_hash(),
+            shared_layout(&images[1]).unwrap().abi_hash()
+        );
+    }
+
+    #[test]
+    fn mismatch() {
+        let td = TempDir::new("microamp-symbols").unwrap();
+        let images = [
+            image(
+                &td,
+                "core0",
+                0x2000_0000,
+                &[("app::X", 0, 4), ("app::Y", 8, 2)],
+                &[],
+            ),
+            image(
+                &td,
+                "core1",
+                0x3000_0000,
+                &[("app::X", 0, 4), ("app::Y", 4, 2)],
+                &[],
+            ),
+        ];
+
+        let error = verify(&images).unwrap_err();
+        let mismatch = error.downcast_ref::<LayoutMismatch>().unwrap();
+        assert_eq!(mismatch.section, ".shared");
+        assert_eq!(mismatch.found.0, images[1]);
+        assert_eq!(mismatch.found.1.get(4).unwrap().name(), Some("app::Y"));
+    }
+}
@@ -5,6 +5,13 @@ main() {
 
     if [ $TARGET = x86_64-unknown-linux-gnu ]; then
         cargo test
+        ( cd build && cargo test )
+
+        # the auto trait needs a nightly compiler; with it the `compile_fail` tests check the
+        # negative implementations instead of the missing opt-in implementations
+        if [ $TRAVIS_RUST_VERSION = nightly ]; then
+            cargo test --features nightly
+        fi
     else
         ( cd dummy && cargo microamp --bin dummy --check )
     fi
@@ -15,4 +22,15 @@ if [ -z ${TARGET-} ]; then
     TARGET=$(rustc -Vv | grep host | cut -d ' ' -f2)
 fi
 
+if [ -z ${TRAVIS_RUST_VERSION-} ]; then
+    case $(rustc -V) in
+        *nightly*)
+            TRAVIS_RUST_VERSION=nightly
+            ;;
+        *)
+            TRAVIS_RUST_VERSION=stable
+            ;;
+    esac
+fi
+
 main
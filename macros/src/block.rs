@@ -0,0 +1,236 @@
+//! The `shared_block!` macro
+
+use proc_macro2::TokenStream;
+use quote::{format_ident, quote};
+use syn::{
+    braced,
+    parse::{self, Parse, ParseStream},
+    punctuated::Punctuated,
+    Attribute, Expr, Ident, Lit, Meta, Token, Type, Visibility,
+};
+
+/// The input of `shared_block!`: a list of blocks
+pub struct Blocks {
+    blocks: Vec<Block>,
+}
+
+/// `#[attrs] vis static NAME: Type { fields }`
+struct Block {
+    attrs: Vec<Attribute>,
+    vis: Visibility,
+    ident: Ident,
+    ty: Ident,
+    fields: Punctuated<Field, Token![,]>,
+}
+
+/// `#[attrs] vis name: Type = expr`
+struct Field {
+    attrs: Vec<Attribute>,
+    /// `#[offset = N]`, the offset the field is expected to be at
+    offset: Option<usize>,
+    vis: Visibility,
+    ident: Ident,
+    ty: Type,
+    expr: Expr,
+}
+
+impl Parse for Blocks {
+    fn parse(input: ParseStream<'_>) -> parse::Result<Self> {
+        let mut blocks = vec![];
+        while !input.is_empty() {
+            blocks.push(input.parse()?);
+        }
+
+        Ok(Blocks { blocks })
+    }
+}
+
+impl Parse for Block {
+    fn parse(input: ParseStream<'_>) -> parse::Result<Self> {
+        let attrs = input.call(Attribute::parse_outer)?;
+        let vis = input.parse()?;
+        input.parse::<Token![static]>()?;
+        if input.peek(Token![mut]) {
+            return Err(input
+                .error("blocks can't be `static mut`; use `SharedCell` fields for mutable data"));
+        }
+        let ident = input.parse()?;
+        input.parse::<Token![:]>()?;
+        let ty = input.parse()?;
+
+        let content;
+        let brace = braced!(content in input);
+        let fields = content.parse_terminated(Field::parse)?;
+        if fields.is_empty() {
+            return Err(parse::Error::new(
+                brace.span,
+                "a block must contain at least one field",
+            ));
+        }
+
+        Ok(Block {
+            attrs,
+            vis,
+            ident,
+            ty,
+            fields,
+        })
+    }
+}
+
+impl Parse for Field {
+    fn parse(input: ParseStream<'_>) -> parse::Result<Self> {
+        let mut attrs = input.call(Attribute::parse_outer)?;
+        let mut offset = None;
+        for attr in attrs.iter().filter(|attr| attr.path.is_ident("offset")) {
+            if offset.is_some() {
+                return Err(parse::Error::new_spanned(
+                    attr,
+                    "`offset` was specified more than once",
+                ));
+            }
+
+            offset = Some(match attr.parse_meta()? {
+                Meta::NameValue(nv) => match nv.lit {
+                    Lit::Int(n) => n.base10_parse()?,
+                    lit => {
+                        return Err(parse::Error::new_spanned(
+                            lit,
+                            "expected an integer literal",
+                        ))
+                    }
+                },
+                meta => return Err(parse::Error::new_spanned(meta, "expected `offset = N`")),
+            });
+        }
+        attrs.retain(|attr| !attr.path.is_ident("offset"));
+
+        Ok(Field {
+            attrs,
+            offset,
+            vis: input.parse()?,
+            ident: input.parse()?,
+            ty: {
+                input.parse::<Token![:]>()?;
+                input.parse()?
+            },
+            expr: {
+                input.parse::<Token![=]>()?;
+                input.parse()?
+            },
+        })
+    }
+}
+
+impl Blocks {
+    pub fn expand(&self) -> TokenStream {
+        let blocks = self.blocks.iter().map(Block::expand);
+        quote!(#(#blocks)*)
+    }
+}
+
+impl Block {
+    fn expand(&self) -> TokenStream {
+        let vis = &self.vis;
+        let ident = &self.ident;
+        let ty = &self.ty;
+
+        // forward the arguments of a `#[shared(..)]` attribute, if there's one
+        let (shared, attrs): (Vec<_>, Vec<_>) = self.attrs.iter().partition(|attr| {
+            attr.path
+                .segments
+                .last()
+                .map(|segment| segment.ident == "shared")
+                .unwrap_or(false)
+        });
+        let shared = if let Some(attr) = shared.first() {
+            let tokens = &attr.tokens;
+            quote!(#[microamp::shared #tokens])
+        } else {
+            quote!(#[microamp::shared])
+        };
+
+        let fields = self.fields.iter().map(|field| {
+            let attrs = &field.attrs;
+            let ident = &field.ident;
+            let ty = &field.ty;
+            quote!(#(#attrs)* #ident: #ty)
+        });
+
+        let inits = self.fields.iter().map(|field| {
+            let ident = &field.ident;
+            let expr = &field.expr;
+            quote!(#ident: #expr)
+        });
+
+        let accessors = self.fields.iter().map(|field| {
+            let vis = &field.vis;
+            let ident = &field.ident;
+            let fty = &field.ty;
+            let offset = format_ident!("{}_OFFSET", ident.to_string().to_uppercase());
+            let doc = format!(
+                "Offset of the `{}` field from the start of the block",
+                ident
+            );
+            quote!(
+                #[doc = #doc]
+                #vis const #offset: usize = core::mem::offset_of!(#ty, #ident);
+
+                #vis fn #ident(&self) -> &#fty {
+                    &self.#ident
+                }
+            )
+        });
+
+        // the offsets of the fields must follow the `repr(C)` rules: each field is placed right
+        // after the previous one, rounded up to its alignment. Fields with an `#[offset]` must
+        // also be at the recorded offset, which external tools may rely on
+        let asserts = self.fields.iter().map(|field| {
+            let ident = &field.ident;
+            let fty = &field.ty;
+            let msg = format!("the offset of `{}::{}` is not the expected one", ty, ident);
+            let recorded = field.offset.map(|expected| {
+                let msg = format!(
+                    "`{}::{}` is not at the recorded offset ({})",
+                    ty, ident, expected
+                );
+                quote!(assert!(offset == #expected, #msg);)
+            });
+            quote!(
+                let align = core::mem::align_of::<#fty>();
+                offset = (offset + align - 1) / align * align;
+                assert!(core::mem::offset_of!(#ty, #ident) == offset, #msg);
+                #recorded
+                offset += core::mem::size_of::<#fty>();
+            )
+        });
+
+        let doc = format!("Layout of the `{}` shared block", ident);
+        quote!(
+            #[doc = #doc]
+            #[repr(C)]
+            #[derive(microamp::DataNotCode)]
+            #[cfg_attr(microamp_heterogeneous, derive(microamp::Shareable))]
+            #vis struct #ty {
+                #(#fields,)*
+            }
+
+            #[allow(dead_code)]
+            impl #ty {
+                #(#accessors)*
+            }
+
+            #[allow(unused_assignments)]
+            const _: () = {
+                let mut offset = 0;
+                #(#asserts)*
+            };
+
+            #(#attrs)*
+            #shared
+            #vis static #ident: #ty = #ty {
+                #(#inits,)*
+            };
+        )
+    }
+}
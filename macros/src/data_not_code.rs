@@ -0,0 +1,95 @@
+//! `#[derive(DataNotCode)]`
+
+use proc_macro2::TokenStream;
+use quote::quote;
+use syn::{parse_quote, Data, DeriveInput, GenericParam, Index, Member, Type};
+
+pub fn expand(mut input: DeriveInput) -> TokenStream {
+    // the fields whose offsets are recorded; the offsets of the fields of enum variants can't be
+    // computed at compile time
+    let mut members = vec![];
+    let fields: Vec<Type> = match &input.data {
+        Data::Struct(data) => {
+            for (i, field) in data.fields.iter().enumerate() {
+                let member = match &field.ident {
+                    Some(ident) => Member::Named(ident.clone()),
+                    None => Member::Unnamed(Index::from(i)),
+                };
+                members.push((member, field.ty.clone()));
+            }
+
+            data.fields.iter().map(|field| field.ty.clone()).collect()
+        }
+        Data::Enum(data) => data
+            .variants
+            .iter()
+            .flat_map(|variant| variant.fields.iter().map(|field| field.ty.clone()))
+            .collect(),
+        Data::Union(data) => {
+            for field in &data.fields.named {
+                let ident = field.ident.clone().expect("unreachable");
+                members.push((Member::Named(ident), field.ty.clone()));
+            }
+
+            data.fields
+                .named
+                .iter()
+                .map(|field| field.ty.clone())
+                .collect()
+        }
+    };
+
+    for param in &mut input.generics.params {
+        if let GenericParam::Type(param) = param {
+            param.bounds.push(parse_quote!(microamp::DataNotCode));
+        }
+    }
+
+    // the fields are recorded in a separate trait because the auto trait (`microamp/nightly`) can't
+    // have associated items. The types of the fields may not implement that trait, e.g. a type
+    // that's `DataNotCode` through the auto trait, so they are looked up through `Probe`
+    let layouts = members.iter().map(|(member, ty)| {
+        let name = match member {
+            Member::Named(ident) => ident.to_string(),
+            Member::Unnamed(index) => index.index.to_string(),
+        };
+
+        quote!(microamp::export::Field {
+            name: #name,
+            offset: core::mem::offset_of!(Self, #member),
+            size: core::mem::size_of::<#ty>(),
+            align: core::mem::align_of::<#ty>(),
+            fields: microamp::export::Probe::<#ty>::FIELDS,
+        })
+    });
+
+    // NOTE the fields are checked in a separate function, rather than in the `where` clause of the
+    // implementation, so that recursive types, like a list whose nodes hold a `SharedPtr` to the
+    // next node, don't make the trait solver go in circles
+    let ident = &input.ident;
+    let name = ident.to_string();
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    quote!(
+        unsafe impl #impl_generics microamp::DataNotCode for #ident #ty_generics #where_clause {}
+
+        const _: () = {
+            #[allow(unused_imports)]
+            use microamp::export::NoFields as _;
+
+            impl #impl_generics microamp::export::Fields for #ident #ty_generics #where_clause {
+                const NAME: &'static str = #name;
+
+                const FIELDS: &'static [microamp::export::Field] = &[#(#layouts),*];
+            }
+        };
+
+        const _: () = {
+            fn assert<T>() where T: microamp::DataNotCode + ?Sized {}
+
+            #[allow(dead_code)]
+            fn check #impl_generics () #where_clause {
+                #(assert::<#fields>();)*
+            }
+        };
+    )
+}
@@ -3,57 +3,371 @@
 #![deny(warnings)]
 #![recursion_limit = "128"]
 
-
-use core::sync::atomic::{AtomicUsize, Ordering};
 use proc_macro::TokenStream;
+use std::collections::BTreeSet;
+
+use proc_macro2::Literal;
+use quote::{format_ident, quote};
+use syn::{
+    parse, parse_macro_input, AttributeArgs, DeriveInput, ItemStatic, Lit, LitStr, Meta,
+    NestedMeta, Path,
+};
+
+use crate::block::Blocks;
 
-use proc_macro2::Span;
-use quote::quote;
-use syn::{parse, parse_macro_input, ItemStatic};
+mod block;
+mod data_not_code;
+mod shareable;
 
 /// An attribute to place a static variable in shared memory
 ///
 /// This static variable will refer to the same memory location on all cores
+///
+/// The variable can be used like a normal static variable; for example, `&X` is a `&'static`
+/// reference. Mutable data can be placed in a non-`mut` static variable of type [`SharedCell`]
+/// instead of a `static mut` variable.
+///
+/// # Arguments
+///
+/// - `section = "name"`, places the variable in the `.shared.name` section instead of the
+///   `.shared` section. Use this to put shared variables in different memories.
+///
+/// - `zeroed`, places the variable in the `.shared.bss` (or `.shared.name.bss`) section. The
+///   initial value must be all zeros; this is checked at compile time. Types that contain padding
+///   bytes are not accepted.
+///
+/// - `uninit`, places the variable in the `.shared.uninit` (or `.shared.name.uninit`) section,
+///   which is never initialized. The variable must be a `static mut` of type `MaybeUninit<T>`.
+///
+/// - `align = N`, aligns the variable to an `N`-byte boundary and pads its size to a multiple of
+///   `N`. `N` must be a power of two. Use this to keep variables written by different cores in
+///   different cache lines, or to align DMA buffers.
+///
+/// - `cores = "2,3"`, makes the variable visible only to the listed cores. The images of the other
+///   cores can't name it.
+///
+/// - `writer = N`, only core `N` can modify the variable. The other cores get a read-only view of
+///   it: a [`ReadOnly`] wrapper. Use `#[cfg(core = "N")]`, not `cfg!`, to separate the code that
+///   writes the variable from the code that reads it.
+///
+/// - `offset = N`, pins the variable at offset `N` of its output section. The variable is placed
+///   in the `.shared.pinned.0xN` (or `.shared.name.pinned.0xN`) section, which the linker script
+///   must put at that offset. `cargo microamp` rejects the images where the variable is not at the
+///   pinned offset.
+///
+/// - `size = N`, the expected size of the variable, in bytes. This is checked at compile time, and
+///   by `cargo microamp` against the linked images.
+///
+/// - `name = "NAME"`, uses `NAME` instead of the name of the variable in its symbol. The symbol of
+///   a shared variable is its path, e.g. `app::ipc::MAILBOX`, so two variables with the same name
+///   declared in different blocks (e.g. function bodies) of one module are rejected unless one of
+///   them is given another name.
+///
+/// [`ReadOnly`]: ../microamp/struct.ReadOnly.html
+/// [`SharedCell`]: ../microamp/struct.SharedCell.html
 #[proc_macro_attribute]
 pub fn shared(args: TokenStream, input: TokenStream) -> TokenStream {
-    static COUNT: AtomicUsize = AtomicUsize::new(0);
-
-    if !args.is_empty() {
-        return parse::Error::new(Span::call_site(), "`#[shared]` takes no arguments")
-            .to_compile_error()
-            .into();
-    }
+    let args = match Args::parse(parse_macro_input!(args as AttributeArgs)) {
+        Ok(args) => args,
+        Err(e) => return e.to_compile_error().into(),
+    };
 
     let item = parse_macro_input!(input as ItemStatic);
 
+    if args.kind == Kind::Uninit && item.mutability.is_none() {
+        return parse::Error::new_spanned(
+            item.static_token,
+            "`uninit` variables must be `static mut`",
+        )
+        .to_compile_error()
+        .into();
+    }
+
+    let section = args.link_section();
     let attrs = &item.attrs;
     let expr = &item.expr;
     let ident = &item.ident;
-    let symbol = format!("{}.{}", ident, COUNT.fetch_add(1, Ordering::AcqRel));
+    let name = match &args.name {
+        Some(name) => name.value(),
+        None => ident.to_string(),
+    };
+    // NOTE the symbol must be the same in all the passes so it can't depend on the order in which
+    // the macro is expanded
+    let symbol = quote!(concat!(module_path!(), "::", #name));
     let ty = &item.ty;
     let vis = &item.vis;
+    let check = match args.kind {
+        Kind::Data => quote!(),
+        Kind::Zeroed => {
+            let msg = format!("the initial value of `{}` is not all zeros", ident);
+            quote!(
+                #[cfg(microamp)]
+                const _: () = assert!(
+                    microamp::export::is_zeroed(&unsafe {
+                        core::mem::transmute::<#ty, [u8; core::mem::size_of::<#ty>()]>(#expr)
+                    }),
+                    #msg
+                );
+            )
+        }
+        Kind::Uninit => quote!(
+            #[cfg(microamp)]
+            const _: () = {
+                fn assert() {
+                    microamp::export::is_uninit::<#ty>();
+                }
+            };
+        ),
+    };
+
+    // `cargo microamp` sets `microamp_heterogeneous` when the cores are compiled for different
+    // targets; then the type must have the same layout on all of them. The data objects carry the
+    // layout of the type, and the atomics it needs, so the tool can check them against the target
+    // of each core that can access the variable
+    let access_cores = args
+        .cores
+        .iter()
+        .flatten()
+        .map(|core| core.parse::<u32>().expect("unreachable"));
+    let shareable = quote!(
+        #[cfg(microamp_heterogeneous)]
+        const _: () = {
+            fn assert() {
+                microamp::export::is_shareable::<#ty>();
+            }
+        };
+
+        #[cfg(all(microamp, microamp_heterogeneous))]
+        const _: () = {
+            const LEN: usize = microamp::export::layout_len::<#ty>(#symbol);
+
+            #[link_section = ".microamp.layout"]
+            #[used]
+            static LAYOUT: [u8; LEN] = microamp::export::layout::<#ty, LEN>(#symbol);
+        };
+
+        #[cfg(all(microamp, microamp_heterogeneous))]
+        const _: () = {
+            const CORES: &[u32] = &[#(#access_cores),*];
+            const LEN: usize = microamp::export::access_len(#symbol, CORES);
+
+            #[link_section = ".microamp.access"]
+            #[used]
+            static ACCESS: [u8; LEN] = microamp::export::access::<#ty, LEN>(#symbol, CORES);
+        };
+    );
+
+    // two variables with the same name in different blocks of one module would get the same
+    // symbol. The compiler rejects the second definition of this marker, whose symbol explains the
+    // problem; NOTE the symbol starts with `!` so that it's reported before the symbol of the
+    // variable, which also collides
+    let unique = quote!(
+        #[cfg(microamp)]
+        const _: () = {
+            #[export_name = concat!(
+                "!two `#[shared]` variables in `", module_path!(), "` are named `", #name,
+                "`; give one of them another name with `#[shared(name = \"..\")]`"
+            )]
+            #[link_section = ".microamp.unique"]
+            static UNIQUE: u8 = 0;
+        };
+    );
+
+    // the tool checks the pins against the linked images; they are passed as absolute symbols
+    let pins = [("offset", args.offset), ("size", args.size)]
+        .iter()
+        .filter_map(|(what, value)| {
+            value.map(|value| {
+                let prefix = format!("microamp.{}.", what);
+                let value = format!("\", {:#x}", value);
+                quote!(
+                    #[cfg(microamp)]
+                    core::arch::global_asm!(concat!(
+                        ".globl \"", #prefix, module_path!(), "::", #name, "\"\n",
+                        ".set \"", #prefix, module_path!(), "::", #name, #value
+                    ));
+                )
+            })
+        })
+        .collect::<Vec<_>>();
+
+    // with `align` or `writer` the variable is defined as a hidden item and the user-facing
+    // variable is declared as an `extern` static in both passes
+    let hidden = args.align.is_some() || args.writer.is_some();
+    let (def, def_attrs, def_vis) = if hidden {
+        (
+            format_ident!("__MICROAMP_{}", ident),
+            quote!(#[allow(non_upper_case_globals)]),
+            quote!(),
+        )
+    } else {
+        (ident.clone(), quote!(#(#attrs)*), quote!(#vis))
+    };
+    let (wrapper, def_ty, def_expr) = if let Some(align) = args.align {
+        let align = Literal::u32_unsuffixed(align);
+        let wrapper = format_ident!("__microamp_align_{}", ident);
+        (
+            quote!(
+                #[allow(dead_code, non_camel_case_types)]
+                #[repr(C, align(#align))]
+                struct #wrapper(#ty);
+            ),
+            quote!(#wrapper),
+            quote!(#wrapper(#expr)),
+        )
+    } else {
+        (quote!(), quote!(#ty), quote!(#expr))
+    };
+
+    let size_check = args.size.map(|size| {
+        let msg = format!("the size of `{}` is not {} bytes", ident, size);
+        let size = Literal::u32_unsuffixed(size);
+        // checked in every pass, not only in the data pass, so the error shows up when a core is
+        // built on its own too
+        quote!(
+            const _: () = assert!(core::mem::size_of::<#def_ty>() == #size, #msg);
+        )
+    });
+
+    // the conditions under which the user-facing variable exists
+    let mut conds = vec![];
+    if !hidden {
+        conds.push(quote!(not(microamp)));
+    }
+    // with `cores` the user-facing variable doesn't exist in the images of the other cores
+    if let Some(cores) = &args.cores {
+        conds.push(quote!(any(microamp, #(core = #cores),*)));
+    }
+
+    let user_cfg;
+    let reader = if let Some(writer) = &args.writer {
+        user_cfg = quote!(#[cfg(all(#(#conds,)* core = #writer))]);
+
+        // the other cores, and the data pass, get a read-only view of the variable
+        let reader_cfg = quote!(#[cfg(all(#(#conds,)* not(core = #writer)))]);
+        quote!(
+            #reader_cfg
+            unsafe extern "C" {
+                #(#attrs)*
+                #[link_name = #symbol]
+                #vis safe static #ident: microamp::ReadOnly<#ty>;
+            }
+        )
+    } else {
+        user_cfg = quote!(#[cfg(all(#(#conds),*))]);
+        quote!()
+    };
+
+    // `shared_ptr!` and `shared_ref!` expand to calls to these functions so only `#[shared]`
+    // variables can be used with them. `X` is declared in both passes, with some type, so the
+    // functions exist in both passes too
+    let helpers_cfg = if let Some(cores) = &args.cores {
+        quote!(#[cfg(any(microamp, #(core = #cores),*))])
+    } else {
+        quote!()
+    };
+    let ptr_fn = format_ident!("__microamp_ptr_{}", ident);
+    let ref_fn = format_ident!("__microamp_ref_{}", ident);
+    let mut helpers = quote!(
+        #helpers_cfg
+        #[doc(hidden)]
+        #[allow(non_snake_case, unused_unsafe)]
+        #vis fn #ptr_fn() -> microamp::SharedPtr<#ty> {
+            // NOTE(cast) the read-only view of a `writer` variable is `#[repr(transparent)]`
+            unsafe { microamp::SharedPtr::from_ptr(core::ptr::addr_of!(#ident).cast::<#ty>()) }
+        }
+    );
+    // a reference to a `static mut` variable, or to a `writer` variable on the writer core, would
+    // alias with its mutable accesses
+    if item.mutability.is_none() && args.writer.is_none() {
+        helpers.extend(quote!(
+            #helpers_cfg
+            #[doc(hidden)]
+            #[allow(non_snake_case)]
+            #vis fn #ref_fn() -> microamp::SharedRef<#ty> {
+                unsafe { microamp::SharedRef::from_ptr(&#ident) }
+            }
+        ));
+    }
+
+    // the tool compares the fingerprint of the type across the data object and all the images;
+    // like the pins they are passed as absolute symbols. The data object and the images use
+    // different prefixes so the image can carry both. The name of the type comes from its
+    // definition, not from how `#ty` spells it
+    let fingerprint = quote!(
+        #helpers_cfg
+        const _: () = {
+            #[allow(unused_imports)]
+            use microamp::export::NoFields as _;
+
+            const NAME: &str = microamp::export::Probe::<#ty>::NAME;
+            #[cfg(microamp_heterogeneous)]
+            const FIELDS: &[microamp::export::Field] = <#ty as microamp::Shareable>::FIELDS;
+            #[cfg(not(microamp_heterogeneous))]
+            const FIELDS: &[microamp::export::Field] = microamp::export::Probe::<#ty>::FIELDS;
+            const FINGERPRINT: u32 = microamp::export::fingerprint::<#ty>(NAME, FIELDS);
+
+            #[cfg(microamp)]
+            core::arch::global_asm!(
+                concat!(
+                    ".globl \"microamp.fingerprint.data.", module_path!(), "::", #name, "\"\n",
+                    ".set \"microamp.fingerprint.data.", module_path!(), "::", #name, "\", {}"
+                ),
+                const FINGERPRINT,
+            );
+
+            #[cfg(not(microamp))]
+            core::arch::global_asm!(
+                concat!(
+                    ".globl \"microamp.fingerprint.image.", module_path!(), "::", #name, "\"\n",
+                    ".set \"microamp.fingerprint.image.", module_path!(), "::", #name, "\", {}"
+                ),
+                const FINGERPRINT,
+            );
+        };
+    );
+
     if item.mutability.is_some() {
         quote!(
             #[cfg(not(target_arch = "arm"))]
             compile_error!("Only the ARM architecture is supported at the moment");
 
-            #(#attrs)*
+            #check
+
+            #shareable
+
+            #unique
+
+            #(#pins)*
+
+            #fingerprint
+
+            #wrapper
+
+            #size_check
+
+            #def_attrs
             #[cfg(microamp)]
-            #[link_section = ".shared"]
+            #[link_section = #section]
             #[export_name = #symbol]
-            static mut #ident: #ty = {
+            #def_vis static mut #def: #def_ty = {
                 fn assert() {
                     microamp::export::is_data::<#ty>();
                 }
 
-                #expr
+                #def_expr
             };
 
-            #[cfg(not(microamp))]
+            #user_cfg
             extern "C" {
+                #(#attrs)*
                 #[link_name = #symbol]
                 #vis static mut #ident: #ty;
             }
+
+            #reader
+
+            #helpers
         )
         .into()
     } else {
@@ -61,38 +375,479 @@ pub fn shared(args: TokenStream, input: TokenStream) -> TokenStream {
             #[cfg(not(target_arch = "arm"))]
             compile_error!("Only the ARM architecture is supported at the moment");
 
-            #(#attrs)*
+            #check
+
+            #shareable
+
+            #unique
+
+            #(#pins)*
+
+            #fingerprint
+
+            #wrapper
+
+            #size_check
+
+            #def_attrs
             #[cfg(microamp)]
-            #[link_section = ".shared"]
-            #[no_mangle]
-            static #ident: #ty = {
+            #[link_section = #section]
+            #[export_name = #symbol]
+            #def_vis static #def: #def_ty = {
                 fn assert() {
                     microamp::export::is_data::<#ty>();
                 }
 
-                #expr
+                #def_expr
             };
 
+            // the variable is shared between cores so it must be `Sync`, just like the definition
+            // in the data pass
             #[cfg(not(microamp))]
-            #vis struct #ident;
-
-            #[cfg(not(microamp))]
-            impl core::ops::Deref for #ident {
-                type Target = #ty;
+            const _: () = {
+                fn assert<T>() where T: Sync {}
 
-                fn deref(&self) -> &#ty {
-                    #[inline(always)]
-                    fn assert<T>() where T: Sync {}
+                fn check() {
                     assert::<#ty>();
+                }
+            };
+
+            #user_cfg
+            unsafe extern "C" {
+                #(#attrs)*
+                #[link_name = #symbol]
+                #vis safe static #ident: #ty;
+            }
 
-                    extern "C" {
-                        static #ident: #ty;
+            #reader
+
+            #helpers
+        )
+        .into()
+    }
+}
+
+/// Returns a [`SharedPtr`] to a `#[shared]` variable
+///
+/// ``` ignore
+/// #[shared]
+/// static mut POOL: [Node; 16] = [Node::new(); 16];
+///
+/// let node: SharedPtr<Node> = microamp::shared_ptr!(POOL).element(3);
+/// ```
+///
+/// The argument must be the path to a `#[shared]` variable.
+///
+/// [`SharedPtr`]: ../microamp/struct.SharedPtr.html
+#[proc_macro]
+pub fn shared_ptr(input: TokenStream) -> TokenStream {
+    helper_call(parse_macro_input!(input as Path), "__microamp_ptr_")
+}
+
+/// Returns a [`SharedRef`] to a `#[shared]` variable
+///
+/// ``` ignore
+/// #[shared]
+/// static BUFFER: SharedCell<[u8; 64]> = SharedCell::new([0; 64]);
+///
+/// let buffer: SharedRef<SharedCell<[u8; 64]>> = microamp::shared_ref!(BUFFER);
+/// ```
+///
+/// The argument must be the path to a non-`mut` `#[shared]` variable that has no `writer`.
+///
+/// [`SharedRef`]: ../microamp/struct.SharedRef.html
+#[proc_macro]
+pub fn shared_ref(input: TokenStream) -> TokenStream {
+    helper_call(parse_macro_input!(input as Path), "__microamp_ref_")
+}
+
+/// Calls the helper function that `#[shared]` generated next to the variable at `path`
+fn helper_call(mut path: Path, prefix: &str) -> TokenStream {
+    let last = path.segments.last_mut().expect("unreachable");
+    last.ident = format_ident!("{}{}", prefix, last.ident);
+
+    quote!(#path()).into()
+}
+
+/// Declares a block of shared variables with a fixed layout
+///
+/// Each block declares a `#[repr(C)]` struct whose fields are laid out in declaration order, and a
+/// single `#[shared]` static variable of that type.
+///
+/// ``` ignore
+/// microamp::shared_block! {
+///     /// Inter-processor communication area
+///     #[shared(section = "tcm", align = 64)]
+///     pub static IPC: Ipc {
+///         pub head: AtomicUsize = AtomicUsize::new(0),
+///         pub tail: AtomicUsize = AtomicUsize::new(0),
+///         #[offset = 8]
+///         buffer: SharedCell<[u8; 256]> = SharedCell::new([0; 256]),
+///     }
+/// }
+///
+/// fn enqueue(byte: u8) {
+///     let tail = IPC.tail().load(Ordering::Relaxed);
+///     unsafe { IPC.buffer().get_mut()[tail % 256] = byte }
+///     IPC.tail().store(tail + 1, Ordering::Release);
+/// }
+///
+/// // where an external tool finds the write index
+/// const TAIL: usize = Ipc::TAIL_OFFSET;
+/// ```
+///
+/// For each field the struct gets an accessor method with the visibility of the field
+/// (`IPC.head()`) and an associated constant with its offset (`Ipc::HEAD_OFFSET`). The offsets are
+/// checked at compile time; an `#[offset = N]` attribute on a field also checks that the field is
+/// at offset `N`, which is the offset external tools were written against. A `#[shared(..)]`
+/// attribute on the block passes its arguments to the `#[shared]` attribute of the static
+/// variable.
+#[proc_macro]
+pub fn shared_block(input: TokenStream) -> TokenStream {
+    parse_macro_input!(input as Blocks).expand().into()
+}
+
+/// Arguments of the `#[shared]` attribute
+struct Args {
+    /// `section = ".."`
+    section: Option<LitStr>,
+    /// `zeroed` / `uninit`
+    kind: Kind,
+    /// `align = N`
+    align: Option<u32>,
+    /// `cores = ".."`, as `cfg(core)` values
+    cores: Option<Vec<String>>,
+    /// `writer = N`, as a `cfg(core)` value
+    writer: Option<String>,
+    /// `offset = N`
+    offset: Option<u32>,
+    /// `size = N`
+    size: Option<u32>,
+    /// `name = ".."`
+    name: Option<LitStr>,
+}
+
+/// How the variable is initialized
+#[derive(Clone, Copy, PartialEq)]
+enum Kind {
+    /// Initialized with its value when the image is loaded
+    Data,
+    /// Initialized with zeros at boot
+    Zeroed,
+    /// Never initialized
+    Uninit,
+}
+
+impl Args {
+    fn parse(args: AttributeArgs) -> parse::Result<Self> {
+        let mut section = None;
+        let mut kind = None;
+        let mut align = None;
+        let mut cores: Option<Vec<String>> = None;
+        let mut writer = None;
+        let mut offset = None;
+        let mut size = None;
+        let mut name = None;
+
+        for arg in args {
+            match arg {
+                NestedMeta::Meta(Meta::Path(ref path))
+                    if path.is_ident("zeroed") || path.is_ident("uninit") =>
+                {
+                    if kind.is_some() {
+                        return Err(parse::Error::new_spanned(
+                            arg,
+                            "only one of `zeroed` and `uninit` can be specified",
+                        ));
+                    }
+
+                    kind = Some(if path.is_ident("zeroed") {
+                        Kind::Zeroed
+                    } else {
+                        Kind::Uninit
+                    });
+                }
+
+                NestedMeta::Meta(Meta::NameValue(nv)) if nv.path.is_ident("align") => {
+                    if align.is_some() {
+                        return Err(parse::Error::new_spanned(
+                            nv,
+                            "`align` was specified more than once",
+                        ));
+                    }
+
+                    let n = match nv.lit {
+                        Lit::Int(n) => n,
+                        lit => {
+                            return Err(parse::Error::new_spanned(
+                                lit,
+                                "expected an integer literal",
+                            ))
+                        }
+                    };
+
+                    // same limit as `#[repr(align)]`
+                    match n.base10_parse::<u32>() {
+                        Ok(value) if value.is_power_of_two() && value <= 1 << 29 => {
+                            align = Some(value)
+                        }
+                        _ => {
+                            return Err(parse::Error::new_spanned(
+                                n,
+                                "alignment must be a power of two no larger than 2^29",
+                            ))
+                        }
+                    }
+                }
+
+                NestedMeta::Meta(Meta::NameValue(nv))
+                    if nv.path.is_ident("offset") || nv.path.is_ident("size") =>
+                {
+                    let slot = if nv.path.is_ident("offset") {
+                        &mut offset
+                    } else {
+                        &mut size
+                    };
+
+                    if slot.is_some() {
+                        return Err(parse::Error::new_spanned(
+                            &nv,
+                            format!(
+                                "`{}` was specified more than once",
+                                nv.path.get_ident().expect("unreachable")
+                            ),
+                        ));
+                    }
+
+                    *slot = Some(match nv.lit {
+                        Lit::Int(n) => n.base10_parse::<u32>()?,
+                        lit => {
+                            return Err(parse::Error::new_spanned(
+                                lit,
+                                "expected an integer literal",
+                            ))
+                        }
+                    });
+                }
+
+                NestedMeta::Meta(Meta::NameValue(nv)) if nv.path.is_ident("writer") => {
+                    if writer.is_some() {
+                        return Err(parse::Error::new_spanned(
+                            nv,
+                            "`writer` was specified more than once",
+                        ));
+                    }
+
+                    let n = match nv.lit {
+                        Lit::Int(n) => n,
+                        lit => {
+                            return Err(parse::Error::new_spanned(
+                                lit,
+                                "expected an integer literal",
+                            ))
+                        }
+                    };
+
+                    writer = Some((n.base10_parse::<u32>()?.to_string(), n));
+                }
+
+                NestedMeta::Meta(Meta::NameValue(nv)) if nv.path.is_ident("cores") => {
+                    if cores.is_some() {
+                        return Err(parse::Error::new_spanned(
+                            nv,
+                            "`cores` was specified more than once",
+                        ));
+                    }
+
+                    let list = match nv.lit {
+                        Lit::Str(list) => list,
+                        lit => {
+                            return Err(parse::Error::new_spanned(lit, "expected a string literal"))
+                        }
+                    };
+
+                    let mut indices = BTreeSet::new();
+                    for core in list.value().split(',') {
+                        let index = core.trim().parse::<u32>().map_err(|_| {
+                            parse::Error::new_spanned(
+                                &list,
+                                format!("`{}` is not a valid core index", core.trim()),
+                            )
+                        })?;
+
+                        if !indices.insert(index) {
+                            return Err(parse::Error::new_spanned(
+                                &list,
+                                format!("core {} was listed more than once", index),
+                            ));
+                        }
+                    }
+
+                    cores = Some(indices.iter().map(|i| i.to_string()).collect());
+                }
+
+                NestedMeta::Meta(Meta::NameValue(nv)) if nv.path.is_ident("name") => {
+                    if name.is_some() {
+                        return Err(parse::Error::new_spanned(
+                            nv,
+                            "`name` was specified more than once",
+                        ));
+                    }
+
+                    let lit = match nv.lit {
+                        Lit::Str(lit) => lit,
+                        lit => {
+                            return Err(parse::Error::new_spanned(lit, "expected a string literal"))
+                        }
+                    };
+
+                    let value = lit.value();
+                    if value.is_empty()
+                        || !value.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+                    {
+                        return Err(parse::Error::new_spanned(
+                            lit,
+                            "names may only contain ASCII letters, digits and underscores",
+                        ));
+                    }
+
+                    name = Some(lit);
+                }
+
+                NestedMeta::Meta(Meta::NameValue(nv)) if nv.path.is_ident("section") => {
+                    if section.is_some() {
+                        return Err(parse::Error::new_spanned(
+                            nv,
+                            "`section` was specified more than once",
+                        ));
+                    }
+
+                    let name = match nv.lit {
+                        Lit::Str(name) => name,
+                        lit => {
+                            return Err(parse::Error::new_spanned(lit, "expected a string literal"))
+                        }
+                    };
+
+                    let value = name.value();
+                    if value.is_empty()
+                        || !value.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+                    {
+                        return Err(parse::Error::new_spanned(
+                            name,
+                            "section names may only contain ASCII letters, digits and underscores",
+                        ));
+                    }
+
+                    if value == "bss" || value == "uninit" || value == "pinned" {
+                        return Err(parse::Error::new_spanned(
+                            name,
+                            "`bss`, `uninit` and `pinned` are reserved section names; \
+                             use the `zeroed`, `uninit` and `offset` arguments instead",
+                        ));
                     }
 
-                    unsafe { &#ident }
+                    section = Some(name);
+                }
+
+                _ => {
+                    return Err(parse::Error::new_spanned(
+                        arg,
+                        "unknown argument; expected `section = \"..\"`, `zeroed`, `uninit`, \
+                         `align = N`, `cores = \"..\"`, `writer = N`, `offset = N`, `size = N` \
+                         or `name = \"..\"`",
+                    ))
                 }
             }
-        )
-        .into()
+        }
+
+        if let (Some(cores), Some((writer, n))) = (&cores, &writer) {
+            if !cores.contains(writer) {
+                return Err(parse::Error::new_spanned(
+                    n,
+                    "the writer must be one of the `cores`",
+                ));
+            }
+        }
+
+        Ok(Args {
+            section,
+            kind: kind.unwrap_or(Kind::Data),
+            align,
+            cores,
+            writer: writer.map(|(writer, _)| writer),
+            offset,
+            size,
+            name,
+        })
+    }
+
+    /// The link section the variable goes in
+    fn link_section(&self) -> String {
+        let mut section = if let Some(section) = &self.section {
+            format!(".shared.{}", section.value())
+        } else {
+            ".shared".to_owned()
+        };
+
+        match self.kind {
+            Kind::Data => {}
+            Kind::Zeroed => section.push_str(".bss"),
+            Kind::Uninit => section.push_str(".uninit"),
+        }
+
+        // a pinned variable gets an input section of its own so the linker script can place it
+        if let Some(offset) = self.offset {
+            section.push_str(&format!(".pinned.{:#x}", offset));
+        }
+
+        section
+    }
+}
+
+/// Implements `DataNotCode` for a type that can be placed in shared memory
+///
+/// ``` ignore
+/// #[derive(DataNotCode)]
+/// struct Node {
+///     value: u32,
+///     next: SharedPtr<Node>,
+/// }
+/// ```
+///
+/// All the fields must be `DataNotCode`. Only needed when `microamp` is used without its `nightly`
+/// feature; with it every type that contains neither function pointers, trait objects,
+/// references nor raw pointers is `DataNotCode`.
+#[proc_macro_derive(DataNotCode)]
+pub fn derive_data_not_code(input: TokenStream) -> TokenStream {
+    data_not_code::expand(parse_macro_input!(input as DeriveInput)).into()
+}
+
+/// Implements `Shareable` for a type whose layout doesn't depend on the compilation target
+///
+/// ``` ignore
+/// #[derive(Clone, Copy, Shareable)]
+/// #[repr(C)]
+/// struct Message {
+///     id: u16,
+///     len: u16,
+///     data: [u8; 60],
+/// }
+///
+/// #[derive(Shareable)]
+/// #[repr(u8)]
+/// enum State {
+///     Idle,
+///     Busy,
+/// }
+/// ```
+///
+/// Structs must be `#[repr(C)]` or `#[repr(transparent)]`, unions must be `#[repr(C)]` and enums
+/// must have an integer representation. All the fields must be `Shareable`.
+#[proc_macro_derive(Shareable)]
+pub fn derive_shareable(input: TokenStream) -> TokenStream {
+    match shareable::expand(parse_macro_input!(input as DeriveInput)) {
+        Ok(tokens) => tokens.into(),
+        Err(e) => e.to_compile_error().into(),
     }
 }
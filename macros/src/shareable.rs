@@ -0,0 +1,141 @@
+//! `#[derive(Shareable)]`
+
+use proc_macro2::TokenStream;
+use quote::quote;
+use syn::{
+    parse, parse_quote, Attribute, Data, DeriveInput, GenericParam, Index, Member, Meta,
+    NestedMeta, Type,
+};
+
+/// The integer representations of an enum that fix the size of its discriminant
+const INTEGERS: &[&str] = &[
+    "u8", "u16", "u32", "u64", "i8", "i16", "i32", "i64", "usize", "isize",
+];
+
+pub fn expand(mut input: DeriveInput) -> parse::Result<TokenStream> {
+    let reprs = reprs(&input.attrs)?;
+    let has = |name: &str| reprs.iter().any(|repr| repr == name);
+
+    // the fields whose offsets are recorded; the offsets of the fields of enum variants can't be
+    // computed at compile time
+    let mut members = vec![];
+    let fields: Vec<Type> = match &input.data {
+        Data::Struct(data) => {
+            if !has("C") && !has("transparent") {
+                return Err(parse::Error::new(
+                    data.struct_token.span,
+                    "`Shareable` structs must be `#[repr(C)]` or `#[repr(transparent)]`",
+                ));
+            }
+
+            for (i, field) in data.fields.iter().enumerate() {
+                let member = match &field.ident {
+                    Some(ident) => Member::Named(ident.clone()),
+                    None => Member::Unnamed(Index::from(i)),
+                };
+                members.push((member, field.ty.clone()));
+            }
+
+            data.fields.iter().map(|field| field.ty.clone()).collect()
+        }
+
+        Data::Enum(data) => {
+            // NOTE the discriminant of a `#[repr(C)]` enum has the size of a C `enum`, which
+            // depends on the target
+            if !INTEGERS.iter().any(|int| has(int)) {
+                return Err(parse::Error::new(
+                    data.enum_token.span,
+                    "`Shareable` enums must have an integer representation, like `#[repr(u8)]`",
+                ));
+            }
+
+            data.variants
+                .iter()
+                .flat_map(|variant| variant.fields.iter().map(|field| field.ty.clone()))
+                .collect()
+        }
+
+        Data::Union(data) => {
+            if !has("C") {
+                return Err(parse::Error::new(
+                    data.union_token.span,
+                    "`Shareable` unions must be `#[repr(C)]`",
+                ));
+            }
+
+            for field in &data.fields.named {
+                let ident = field.ident.clone().expect("unreachable");
+                members.push((Member::Named(ident), field.ty.clone()));
+            }
+
+            data.fields
+                .named
+                .iter()
+                .map(|field| field.ty.clone())
+                .collect()
+        }
+    };
+
+    // like the built-in derives: every type parameter must implement the trait
+    for param in &mut input.generics.params {
+        if let GenericParam::Type(param) = param {
+            param.bounds.push(parse_quote!(microamp::Shareable));
+        }
+    }
+
+    let layouts = members.iter().map(|(member, ty)| {
+        let name = match member {
+            Member::Named(ident) => ident.to_string(),
+            Member::Unnamed(index) => index.index.to_string(),
+        };
+
+        quote!(microamp::export::Field {
+            name: #name,
+            offset: core::mem::offset_of!(Self, #member),
+            size: <#ty as microamp::Shareable>::SIZE,
+            align: <#ty as microamp::Shareable>::ALIGN,
+            fields: <#ty as microamp::Shareable>::FIELDS,
+        })
+    });
+
+    let ident = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    Ok(quote!(
+        unsafe impl #impl_generics microamp::Shareable for #ident #ty_generics #where_clause {
+            const FIELDS: &'static [microamp::export::Field] = &[#(#layouts),*];
+
+            const ATOMICS: u8 = 0 #(| <#fields as microamp::Shareable>::ATOMICS)*;
+        }
+
+        const _: () = {
+            fn assert<T>() where T: microamp::Shareable {}
+
+            #[allow(dead_code)]
+            fn check #impl_generics () #where_clause {
+                #(assert::<#fields>();)*
+            }
+        };
+    ))
+}
+
+/// The representations listed in the `#[repr(..)]` attributes
+fn reprs(attrs: &[Attribute]) -> parse::Result<Vec<String>> {
+    let mut reprs = vec![];
+    for attr in attrs {
+        if !attr.path.is_ident("repr") {
+            continue;
+        }
+
+        if let Meta::List(list) = attr.parse_meta()? {
+            for nested in list.nested {
+                if let NestedMeta::Meta(meta) = nested {
+                    if let Some(ident) = meta.path().get_ident() {
+                        reprs.push(ident.to_string());
+                    }
+                }
+            }
+        }
+    }
+
+    Ok(reprs)
+}
@@ -0,0 +1,151 @@
+use core::{
+    cell::UnsafeCell,
+    fmt, ptr,
+    sync::atomic::{self, Ordering},
+};
+
+// NOTE `cargo microamp` overwrites the value in the linked images with the hash of the verified
+// layout of the shared sections. `.rodata.*` so it's part of the load image
+#[allow(non_upper_case_globals)]
+#[link_section = ".rodata.__microamp_abi"]
+#[no_mangle]
+#[used]
+static __microamp_abi: u32 = 0;
+
+/// Returns the hash of the layout of the shared sections this image was linked with
+///
+/// `cargo microamp` computes the hash after verifying that the layout is the same on all the
+/// images, and embeds it in all of them. Images built with the same layout have the same hash. The
+/// hash is never 0, except in images that were not built by `cargo microamp`.
+pub fn abi_hash() -> u32 {
+    // NOTE(volatile) the value is patched after compilation
+    unsafe { ptr::read_volatile(ptr::addr_of!(__microamp_abi)) }
+}
+
+/// A slot, in shared memory, that holds the [`abi_hash`] of the core that owns the shared data
+///
+/// The owner core, usually the one that initializes the shared sections, [`publish`]es its hash
+/// at boot. The other cores [`check`] theirs against it before touching any other shared variable
+/// so that flashing a new image on only some of the cores is detected.
+///
+/// ``` ignore
+/// use microamp::{shared, AbiHash};
+///
+/// #[shared]
+/// static ABI: AbiHash = AbiHash::new();
+///
+/// #[entry]
+/// fn main() -> ! {
+///     if cfg!(core = "0") {
+///         ABI.publish();
+///     } else if let Err(e) = ABI.wait() {
+///         panic!("{}", e);
+///     }
+///
+///     // ..
+/// }
+/// ```
+///
+/// The hash is stored next to its bitwise complement so that memory that was never initialized is
+/// very unlikely to look like a published hash. A hash published in a previous boot is still
+/// valid though, so the slot must be cleared before the other cores start: don't place it in an
+/// `uninit` section, and make sure the other cores can't run before the shared section that holds
+/// it has been initialized.
+///
+/// [`publish`]: struct.AbiHash.html#method.publish
+/// [`check`]: struct.AbiHash.html#method.check
+#[repr(C)]
+pub struct AbiHash {
+    hash: UnsafeCell<u32>,
+    /// `!hash` once the hash has been published
+    marker: UnsafeCell<u32>,
+}
+
+// NOTE(Sync) the slot is only accessed with volatile operations
+unsafe impl Sync for AbiHash {}
+
+impl AbiHash {
+    /// Creates an empty slot
+    pub const fn new() -> Self {
+        AbiHash {
+            hash: UnsafeCell::new(0),
+            marker: UnsafeCell::new(0),
+        }
+    }
+
+    /// Stores the hash of this image in the slot
+    pub fn publish(&self) {
+        let hash = abi_hash();
+        unsafe { ptr::write_volatile(self.hash.get(), hash) }
+        // NOTE the marker is written last so the hash is never seen without it
+        atomic::fence(Ordering::SeqCst);
+        unsafe { ptr::write_volatile(self.marker.get(), !hash) }
+        atomic::fence(Ordering::SeqCst);
+    }
+
+    /// Returns the hash stored in the slot, or `None` if no core has published its hash yet
+    pub fn published(&self) -> Option<u32> {
+        atomic::fence(Ordering::SeqCst);
+        let marker = unsafe { ptr::read_volatile(self.marker.get()) };
+        atomic::fence(Ordering::SeqCst);
+        let hash = unsafe { ptr::read_volatile(self.hash.get()) };
+
+        if hash != 0 && marker == !hash {
+            Some(hash)
+        } else {
+            None
+        }
+    }
+
+    /// Compares the hash of this image with the one stored in the slot
+    ///
+    /// It's an error if no core has published its hash yet; see [`wait`]
+    ///
+    /// [`wait`]: struct.AbiHash.html#method.wait
+    pub fn check(&self) -> Result<(), AbiMismatch> {
+        let own = abi_hash();
+        match self.published() {
+            Some(shared) if shared == own => Ok(()),
+            shared => Err(AbiMismatch { own, shared }),
+        }
+    }
+
+    /// Waits until a core publishes its hash and then compares the hash of this image with it
+    ///
+    /// See the type-level documentation for how the slot must be initialized; a slot that holds
+    /// a hash published in a previous boot makes this return immediately
+    pub fn wait(&self) -> Result<(), AbiMismatch> {
+        while self.published().is_none() {}
+
+        self.check()
+    }
+}
+
+impl Default for AbiHash {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The images of two cores were linked with different layouts of the shared sections
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct AbiMismatch {
+    /// The hash of this image
+    pub own: u32,
+    /// The hash stored in shared memory, or `None` if no core has published its hash
+    pub shared: Option<u32>,
+}
+
+impl fmt::Display for AbiMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.shared {
+            Some(shared) => write!(
+                f,
+                "the shared memory layout of this image ({:#010x}) doesn't match the one in \
+                 shared memory ({:#010x})",
+                self.own, shared
+            ),
+            None => f.write_str("no core has published the layout of the shared memory"),
+        }
+    }
+}
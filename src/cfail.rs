@@ -1,3 +1,7 @@
+// NOTE without the `nightly` feature the function pointer, reference and raw pointer types are
+// rejected because they don't opt in to `DataNotCode`; run these tests with `--features nightly`
+// too to check the negative implementations of the auto trait
+
 //! ``` compile_fail
 //! microamp::export::is_data::<fn()>();
 //! ```
@@ -7,6 +11,161 @@
 //! ```
 //!
 //! ``` compile_fail
+//! microamp::export::is_data::<unsafe fn(u8) -> u8>();
+//! ```
+//!
+//! ``` compile_fail
+//! microamp::export::is_data::<extern "C" fn(u32)>();
+//! ```
+//!
+//! ``` compile_fail
+//! microamp::export::is_data::<extern "system" fn(u32)>();
+//! ```
+//!
+//! ``` compile_fail
+//! microamp::export::is_data::<extern "C-unwind" fn()>();
+//! ```
+//!
+//! ``` compile_fail
+//! microamp::export::is_data::<for<'a> fn(&'a u8) -> &'a u8>();
+//! ```
+//!
+//! ``` compile_fail
+//! microamp::export::is_data::<unsafe extern "C" fn(i32, ...) -> i32>();
+//! ```
+//!
+//! ``` compile_fail
+//! microamp::export::is_data::<
+//!     fn(u8, u8, u8, u8, u8, u8, u8, u8, u8, u8, u8, u8, u8, u8, u8, u8, u8, u8, u8, u8),
+//! >();
+//! ```
+//!
+//! ``` compile_fail
 //! trait Foo {}
 //! microamp::export::is_data::<dyn Foo>();
 //! ```
+//!
+//! ``` compile_fail
+//! microamp::export::is_data::<&'static u32>();
+//! ```
+//!
+//! ``` compile_fail
+//! microamp::export::is_data::<&'static mut [u8]>();
+//! ```
+//!
+//! ``` compile_fail
+//! microamp::export::is_data::<*const u8>();
+//! ```
+//!
+//! ``` compile_fail
+//! microamp::export::is_data::<*mut u8>();
+//! ```
+//!
+//! ``` compile_fail
+//! microamp::export::is_data::<core::sync::atomic::AtomicPtr<u8>>();
+//! ```
+//!
+//! ``` compile_fail
+//! microamp::export::is_data::<microamp::SharedPtr<fn()>>();
+//! ```
+//!
+//! ``` compile_fail
+//! microamp::export::is_data::<microamp::SharedRef<&'static u8>>();
+//! ```
+//!
+//! ``` compile_fail
+//! struct Descriptor {
+//!     len: usize,
+//!     buffer: &'static [u8],
+//! }
+//!
+//! microamp::export::is_data::<Descriptor>();
+//! ```
+//!
+//! ``` compile_fail
+//! use core::sync::atomic::{AtomicU32, Ordering};
+//!
+//! fn store(x: &microamp::ReadOnly<AtomicU32>) {
+//!     x.store(1, Ordering::Relaxed);
+//! }
+//! ```
+//!
+//! ``` compile_fail
+//! microamp::export::is_data::<microamp::SharedCell<fn()>>();
+//! ```
+//!
+//! ``` compile_fail
+//! microamp::export::is_shareable::<(u8, u16)>();
+//! ```
+//!
+//! ``` compile_fail
+//! microamp::export::is_shareable::<u128>();
+//! ```
+//!
+//! ``` compile_fail
+//! #[derive(microamp::Shareable)]
+//! struct Message {
+//!     id: u16,
+//!     len: u16,
+//! }
+//! ```
+//!
+//! ``` compile_fail
+//! #[derive(microamp::Shareable)]
+//! #[repr(C)]
+//! enum State {
+//!     Idle,
+//!     Busy,
+//! }
+//! ```
+//!
+//! ``` compile_fail
+//! struct Opaque(u32);
+//!
+//! #[derive(microamp::Shareable)]
+//! #[repr(C)]
+//! struct Message {
+//!     id: u16,
+//!     payload: Opaque,
+//! }
+//! ```
+//!
+//! ``` compile_fail
+//! #[derive(microamp::DataNotCode)]
+//! struct Descriptor {
+//!     len: usize,
+//!     buffer: &'static [u8],
+//! }
+//! ```
+//!
+//! ``` compile_fail
+//! #[derive(microamp::DataNotCode)]
+//! struct Callback {
+//!     context: u32,
+//!     handler: extern "C" fn(u32),
+//! }
+//! ```
+//!
+//! ``` compile_fail
+//! #[derive(microamp::DataNotCode)]
+//! struct Node {
+//!     value: u32,
+//!     next: *mut Node,
+//! }
+//! ```
+//!
+//! ``` compile_fail,E0080
+//! microamp::shared_block! {
+//!     static MAILBOX: Mailbox {
+//!         ready: u8 = 0,
+//!         // `repr(C)` puts `len` at offset 4
+//!         #[offset = 1]
+//!         len: u32 = 0,
+//!     }
+//! }
+//! ```
+//!
+//! ``` compile_fail,E0080
+//! #[microamp::shared(offset = 0x40, size = 2)]
+//! static X: u32 = 0;
+//! ```
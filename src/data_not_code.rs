@@ -0,0 +1,11 @@
+// NOTE the auto trait lives in its own file because the compiler parses the unstable syntax even
+// when the code is disabled with `cfg`
+#[cfg(feature = "nightly")]
+mod auto;
+#[cfg(not(feature = "nightly"))]
+mod opt_in;
+
+#[cfg(feature = "nightly")]
+pub use self::auto::DataNotCode;
+#[cfg(not(feature = "nightly"))]
+pub use self::opt_in::DataNotCode;
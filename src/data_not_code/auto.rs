@@ -0,0 +1,58 @@
+/// Types that can be placed in shared memory
+///
+/// Function pointers, trait objects, references and raw pointers are not `DataNotCode` because
+/// their values can point to memory that's private to one core, like a static variable that's
+/// instantiated once per image. Every type that doesn't contain one of them implements this auto
+/// trait.
+///
+/// # Limitations
+///
+/// Only function pointers with up to 128 arguments are rejected; function pointers with more
+/// arguments implement this trait.
+///
+/// # Safety
+///
+/// The type must not contain a function pointer, a trait object, a reference or a raw pointer
+#[diagnostic::on_unimplemented(
+    message = "`{Self}` can't be placed in shared memory",
+    note = "function pointers, trait objects, references and raw pointers are rejected because \
+            their values can point to memory that's private to one core"
+)]
+pub unsafe auto trait DataNotCode {}
+
+// references and raw pointers very likely point to a static variable that's instantiated once
+// per core and has a different address on each image
+impl<T: ?Sized> !DataNotCode for &T {}
+impl<T: ?Sized> !DataNotCode for &mut T {}
+impl<T: ?Sized> !DataNotCode for *const T {}
+impl<T: ?Sized> !DataNotCode for *mut T {}
+
+// NOTE(arity) rustc doesn't use the auto implementation of `DataNotCode` for a function pointer
+// type if there's an explicit implementation for *any* function pointer type with the same number
+// of arguments; the ABI, `unsafe`, higher-ranked lifetimes and C-variadic arguments are not taken
+// into account. So a single negative implementation per arity rejects all the function pointers
+// with that number of arguments
+macro_rules! impls {
+    ($head:tt $($tail:tt)*) => {
+        impls!($($tail)*);
+        impl<R> !DataNotCode for fn($head $(,$tail)*) -> R {}
+    };
+
+    () => {
+        impl<R> !DataNotCode for fn() -> R {}
+    };
+}
+
+// FIXME this should use Variadic Generics; for now reject up to 128 arguments, which is above the
+// minimum number of parameters a C compiler must support (127). Keep the limit in the docs above
+// in sync
+impls! {
+    () () () () () () () () () () () () () () () ()
+    () () () () () () () () () () () () () () () ()
+    () () () () () () () () () () () () () () () ()
+    () () () () () () () () () () () () () () () ()
+    () () () () () () () () () () () () () () () ()
+    () () () () () () () () () () () () () () () ()
+    () () () () () () () () () () () () () () () ()
+    () () () () () () () () () () () () () () () ()
+}
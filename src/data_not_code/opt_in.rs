@@ -0,0 +1,131 @@
+use core::{
+    cell::{Cell, UnsafeCell},
+    marker::PhantomData,
+    mem::{ManuallyDrop, MaybeUninit},
+    num::{self, Wrapping},
+    sync::atomic,
+};
+
+use crate::{AbiHash, ReadOnly, SharedCell};
+
+/// Types that can be placed in shared memory
+///
+/// Function pointers, trait objects, references and raw pointers are not `DataNotCode` because
+/// their values can point to memory that's private to one core, like a static variable that's
+/// instantiated once per image.
+///
+/// The trait is implemented for the primitive types, atomics, arrays, tuples and the `core` and
+/// `microamp` wrappers; use `#[derive(DataNotCode)]` to implement it for your own types. With the
+/// `nightly` feature this is an auto trait instead, implemented by every type that doesn't
+/// contain one of the rejected types; the auto trait only rejects function pointers with up to
+/// 128 arguments.
+///
+/// # Safety
+///
+/// The type must not contain a function pointer, a trait object, a reference or a raw pointer
+#[diagnostic::on_unimplemented(
+    message = "`{Self}` can't be placed in shared memory",
+    note = "use `#[derive(microamp::DataNotCode)]` on types that contain neither function \
+            pointers, trait objects, references nor raw pointers"
+)]
+pub unsafe trait DataNotCode {}
+
+// Without auto traits the types are opted in one by one; anything that's not listed here, or
+// derived, is rejected, which is stricter than the auto trait
+macro_rules! primitives {
+    ($($T:ty,)+) => {
+        $(
+            unsafe impl DataNotCode for $T {}
+        )+
+    };
+}
+
+primitives! {
+    (),
+    bool,
+    char,
+    f32,
+    f64,
+    i8,
+    i16,
+    i32,
+    i64,
+    i128,
+    isize,
+    u8,
+    u16,
+    u32,
+    u64,
+    u128,
+    usize,
+    str,
+    num::NonZeroI8,
+    num::NonZeroI16,
+    num::NonZeroI32,
+    num::NonZeroI64,
+    num::NonZeroI128,
+    num::NonZeroIsize,
+    num::NonZeroU8,
+    num::NonZeroU16,
+    num::NonZeroU32,
+    num::NonZeroU64,
+    num::NonZeroU128,
+    num::NonZeroUsize,
+    atomic::AtomicBool,
+    atomic::AtomicI8,
+    atomic::AtomicI16,
+    atomic::AtomicI32,
+    atomic::AtomicIsize,
+    atomic::AtomicU8,
+    atomic::AtomicU16,
+    atomic::AtomicU32,
+    atomic::AtomicUsize,
+    AbiHash,
+}
+
+#[cfg(target_has_atomic = "64")]
+primitives! {
+    atomic::AtomicI64,
+    atomic::AtomicU64,
+}
+
+unsafe impl<T, const N: usize> DataNotCode for [T; N] where T: DataNotCode {}
+
+unsafe impl<T> DataNotCode for [T] where T: DataNotCode {}
+
+// the wrappers contain a value of the wrapped type
+macro_rules! wrappers {
+    ($($Wrapper:ident,)+) => {
+        $(
+            unsafe impl<T> DataNotCode for $Wrapper<T> where T: DataNotCode {}
+        )+
+    };
+}
+
+wrappers! {
+    Option,
+    Wrapping,
+    PhantomData,
+    Cell,
+    UnsafeCell,
+    MaybeUninit,
+    ManuallyDrop,
+    SharedCell,
+    ReadOnly,
+}
+
+macro_rules! tuples {
+    ($head:ident $($tail:ident)*) => {
+        tuples!($($tail)*);
+        unsafe impl<$head $(, $tail)*> DataNotCode for ($head, $($tail,)*)
+        where
+            $head: DataNotCode,
+            $($tail: DataNotCode,)*
+        {
+        }
+    };
+
+    () => {};
+}
+
+tuples!(A B C D E F G H I J K L);
@@ -1,28 +1,472 @@
-pub auto trait DataNotCode {}
+use core::{
+    cell::{Cell, UnsafeCell},
+    marker::PhantomData,
+    mem::{self, ManuallyDrop, MaybeUninit},
+    num::{self, Wrapping},
+    sync::atomic,
+};
 
-macro_rules! impl_ {
-    ($($T:ident),*) => {
-        impl<R, $($T),*> !DataNotCode for fn($($T),*) -> R {}
-        impl<R, $($T),*> !DataNotCode for extern "C" fn($($T),*) -> R {}
+use crate::{AbiHash, ReadOnly, SharedCell};
+
+pub use crate::DataNotCode;
+
+pub fn is_data<T>()
+where
+    T: DataNotCode + ?Sized,
+{
+}
+
+pub fn is_shareable<T>()
+where
+    T: crate::Shareable,
+{
+}
+
+/// The layout of a field of a `Shareable` (or, without the `nightly` feature, `DataNotCode`) type
+pub struct Field {
+    pub name: &'static str,
+    pub offset: usize,
+    pub size: usize,
+    pub align: usize,
+    /// The fields of the field's type
+    pub fields: &'static [Field],
+}
+
+// The layout of a shared variable is encoded as a tree of records. Each record is, in little
+// endian: the length of the name (`u16`), the name, the offset, size and alignment (`u32` each),
+// the number of fields (`u16`) and then one record per field. The root record is named after the
+// symbol of the variable. `cargo microamp` parses these records
+
+/// The number of bytes `layout::<T, _>(symbol)` produces
+pub const fn layout_len<T>(symbol: &str) -> usize
+where
+    T: crate::Shareable,
+{
+    record_len(symbol, T::FIELDS)
+}
+
+/// Encodes the layout of the shared variable `symbol`, of type `T`
+pub const fn layout<T, const N: usize>(symbol: &str) -> [u8; N]
+where
+    T: crate::Shareable,
+{
+    let mut buffer = [0; N];
+    let end = write_record(&mut buffer, 0, symbol, 0, T::SIZE, T::ALIGN, T::FIELDS);
+    assert!(end == N);
+    buffer
+}
+
+// How a shared variable is accessed is encoded as a record that contains, in little endian: the
+// length of the symbol (`u16`), the symbol, the atomic widths the type of the variable needs
+// (`u8`), the atomic widths the target supports (`u8`), whether the target is big endian (`u8`),
+// the pointer width of the target in bytes (`u8`), the number of cores that can access the
+// variable (`u16`; zero means all of them) and their indices (`u32` each). The atomic widths are
+// bitmasks of sizes in bytes: 1, 2, 4 and 8
+
+/// The widths of the atomics on which this target can perform compare-and-swap
+const TARGET_ATOMICS: u8 = (cfg!(target_has_atomic = "8") as u8)
+    | (cfg!(target_has_atomic = "16") as u8) << 1
+    | (cfg!(target_has_atomic = "32") as u8) << 2
+    | (cfg!(target_has_atomic = "64") as u8) << 3;
+
+/// The number of bytes `access::<T, _>(symbol, cores)` produces
+pub const fn access_len(symbol: &str, cores: &[u32]) -> usize {
+    2 + symbol.len() + 4 + 2 + 4 * cores.len()
+}
+
+/// Encodes how the shared variable `symbol`, of type `T`, is accessed
+pub const fn access<T, const N: usize>(symbol: &str, cores: &[u32]) -> [u8; N]
+where
+    T: crate::Shareable,
+{
+    let mut buffer = [0; N];
+    let mut pos = write_bytes(&mut buffer, 0, &(symbol.len() as u16).to_le_bytes());
+    pos = write_bytes(&mut buffer, pos, symbol.as_bytes());
+    pos = write_bytes(
+        &mut buffer,
+        pos,
+        &[
+            T::ATOMICS,
+            TARGET_ATOMICS,
+            cfg!(target_endian = "big") as u8,
+            core::mem::size_of::<usize>() as u8,
+        ],
+    );
+    pos = write_bytes(&mut buffer, pos, &(cores.len() as u16).to_le_bytes());
+
+    let mut i = 0;
+    while i < cores.len() {
+        pos = write_bytes(&mut buffer, pos, &cores[i].to_le_bytes());
+        i += 1;
+    }
+
+    assert!(pos == N);
+    buffer
+}
+
+const fn record_len(name: &str, fields: &[Field]) -> usize {
+    let mut len = 2 + name.len() + 3 * 4 + 2;
+    let mut i = 0;
+    while i < fields.len() {
+        len += record_len(fields[i].name, fields[i].fields);
+        i += 1;
+    }
+
+    len
+}
+
+const fn write_record<const N: usize>(
+    buffer: &mut [u8; N],
+    mut pos: usize,
+    name: &str,
+    offset: usize,
+    size: usize,
+    align: usize,
+    fields: &[Field],
+) -> usize {
+    pos = write_bytes(buffer, pos, &(name.len() as u16).to_le_bytes());
+    pos = write_bytes(buffer, pos, name.as_bytes());
+    pos = write_bytes(buffer, pos, &(offset as u32).to_le_bytes());
+    pos = write_bytes(buffer, pos, &(size as u32).to_le_bytes());
+    pos = write_bytes(buffer, pos, &(align as u32).to_le_bytes());
+    pos = write_bytes(buffer, pos, &(fields.len() as u16).to_le_bytes());
+
+    let mut i = 0;
+    while i < fields.len() {
+        let field = &fields[i];
+        pos = write_record(
+            buffer,
+            pos,
+            field.name,
+            field.offset,
+            field.size,
+            field.align,
+            field.fields,
+        );
+        i += 1;
+    }
+
+    pos
+}
+
+/// The name and the fields of a type, for its fingerprint
+///
+/// This is not part of `DataNotCode` because the auto trait (`nightly` feature) can't have
+/// associated items; `#[derive(DataNotCode)]` implements it in both modes
+pub trait Fields {
+    /// The name of the type as written in its definition, without its path or generic arguments,
+    /// so that `Msg` and `crate::Msg` have the same fingerprint
+    const NAME: &'static str;
+
+    const FIELDS: &'static [Field] = &[];
+}
+
+/// `Probe::<T>::NAME` and `Probe::<T>::FIELDS` are the items of `T`'s `Fields` implementation, if
+/// it has one, and the ones of `NoFields` otherwise
+///
+/// The inherent items are picked over the trait ones when `T: Fields` holds, so the probe only
+/// works on concrete types and `NoFields` must be in scope
+pub struct Probe<T>(PhantomData<T>)
+where
+    T: ?Sized;
+
+impl<T> Probe<T>
+where
+    T: Fields + ?Sized,
+{
+    pub const NAME: &'static str = T::NAME;
+
+    pub const FIELDS: &'static [Field] = T::FIELDS;
+}
+
+/// The fallback of `Probe`: no name and no fields, only the size and alignment of the type
+pub trait NoFields {
+    const NAME: &'static str = "";
+
+    const FIELDS: &'static [Field] = &[];
+}
+
+impl<T> NoFields for Probe<T> where T: ?Sized {}
+
+macro_rules! primitives {
+    ($($T:ty,)+) => {
+        $(
+            impl Fields for $T {
+                const NAME: &'static str = stringify!($T);
+            }
+        )+
+    };
+}
+
+primitives! {
+    (),
+    bool,
+    char,
+    f32,
+    f64,
+    i8,
+    i16,
+    i32,
+    i64,
+    i128,
+    isize,
+    u8,
+    u16,
+    u32,
+    u64,
+    u128,
+    usize,
+    str,
+    AbiHash,
+}
+
+// the name of an integer type with a niche, or of an atomic, is its last path segment
+macro_rules! paths {
+    ($($module:ident::$T:ident,)+) => {
+        $(
+            impl Fields for $module::$T {
+                const NAME: &'static str = stringify!($T);
+            }
+        )+
     };
 }
 
-macro_rules! impls {
-    ($head:ident $(,$tail:ident)*) => {
-        impls!($($tail),*);
-        impl_!($head $(,$tail)*);
+paths! {
+    num::NonZeroI8,
+    num::NonZeroI16,
+    num::NonZeroI32,
+    num::NonZeroI64,
+    num::NonZeroI128,
+    num::NonZeroIsize,
+    num::NonZeroU8,
+    num::NonZeroU16,
+    num::NonZeroU32,
+    num::NonZeroU64,
+    num::NonZeroU128,
+    num::NonZeroUsize,
+    atomic::AtomicBool,
+    atomic::AtomicI8,
+    atomic::AtomicI16,
+    atomic::AtomicI32,
+    atomic::AtomicIsize,
+    atomic::AtomicU8,
+    atomic::AtomicU16,
+    atomic::AtomicU32,
+    atomic::AtomicUsize,
+}
+
+#[cfg(target_has_atomic = "64")]
+paths! {
+    atomic::AtomicI64,
+    atomic::AtomicU64,
+}
+
+// the layout of the first element stands for the layout of all of them
+impl<T, const N: usize> Fields for [T; N]
+where
+    T: Fields,
+{
+    const NAME: &'static str = "[T; N]";
+
+    const FIELDS: &'static [Field] = if N == 0 {
+        &[]
+    } else {
+        &[Field {
+            name: "[0]",
+            offset: 0,
+            size: mem::size_of::<T>(),
+            align: mem::align_of::<T>(),
+            fields: T::FIELDS,
+        }]
     };
+}
 
-    () => {
-        impl_!();
+// the wrappers have the layout of the wrapped type, so they are hashed as if they were the wrapped
+// type
+macro_rules! wrappers {
+    ($($Wrapper:ident,)+) => {
+        $(
+            impl<T> Fields for $Wrapper<T>
+            where
+                T: Fields,
+            {
+                const NAME: &'static str = T::NAME;
+
+                const FIELDS: &'static [Field] = T::FIELDS;
+            }
+        )+
     };
 }
 
-// FIXME this should use Variadic Generics
-impls!(A, B, C, D, E, F, G, H, I, J, K, L);
+wrappers! {
+    Wrapping,
+    Cell,
+    UnsafeCell,
+    MaybeUninit,
+    ManuallyDrop,
+    SharedCell,
+    ReadOnly,
+}
 
-pub fn is_data<T>()
+macro_rules! tuples {
+    ($(($($T:ident $i:tt),+),)+) => {
+        $(
+            impl<$($T),+> Fields for ($($T,)+)
+            where
+                $($T: Fields,)+
+            {
+                const NAME: &'static str = "(..)";
+
+                const FIELDS: &'static [Field] = &[$(Field {
+                    name: stringify!($i),
+                    offset: mem::offset_of!(Self, $i),
+                    size: mem::size_of::<$T>(),
+                    align: mem::align_of::<$T>(),
+                    fields: $T::FIELDS,
+                }),+];
+            }
+        )+
+    };
+}
+
+tuples! {
+    (A 0),
+    (A 0, B 1),
+    (A 0, B 1, C 2),
+    (A 0, B 1, C 2, D 3),
+    (A 0, B 1, C 2, D 3, E 4),
+    (A 0, B 1, C 2, D 3, E 4, F 5),
+    (A 0, B 1, C 2, D 3, E 4, F 5, G 6),
+    (A 0, B 1, C 2, D 3, E 4, F 5, G 6, H 7),
+    (A 0, B 1, C 2, D 3, E 4, F 5, G 6, H 7, I 8),
+    (A 0, B 1, C 2, D 3, E 4, F 5, G 6, H 7, I 8, J 9),
+    (A 0, B 1, C 2, D 3, E 4, F 5, G 6, H 7, I 8, J 9, K 10),
+    (A 0, B 1, C 2, D 3, E 4, F 5, G 6, H 7, I 8, J 9, K 10, L 11),
+}
+
+// The fingerprint of the type of a shared variable is the 32-bit FNV-1a hash of the name of the
+// type, its size and alignment, and the names, offsets, sizes and alignments of its fields,
+// recursively. The data object and every image carry the fingerprint as an absolute symbol so
+// `cargo microamp` can tell when the cores see different definitions of the type
+const FNV_OFFSET: u32 = 0x811c_9dc5;
+const FNV_PRIME: u32 = 0x0100_0193;
+
+/// The fingerprint of the type `T`, named `name`, with the given `fields`
+///
+/// `#[shared]` passes the name and the fields that `Probe::<T>` finds, so the fingerprint doesn't
+/// depend on how the type is spelled but does change when its fields are reordered, with or
+/// without the `nightly` feature:
+///
+/// ```
+/// use microamp::export::{fingerprint, Probe};
+///
+/// macro_rules! fingerprint {
+///     ($T:ty) => {
+///         fingerprint::<$T>(Probe::<$T>::NAME, Probe::<$T>::FIELDS)
+///     };
+/// }
+///
+/// mod ipc {
+///     #[derive(microamp::DataNotCode)]
+///     #[repr(C)]
+///     pub struct Msg {
+///         pub len: u16,
+///         pub tag: u16,
+///     }
+/// }
+///
+/// mod old {
+///     #[derive(microamp::DataNotCode)]
+///     #[repr(C)]
+///     pub struct Msg {
+///         pub tag: u16,
+///         pub len: u16,
+///     }
+/// }
+///
+/// use ipc::Msg;
+///
+/// fn main() {
+///     assert_eq!(fingerprint!(Msg), fingerprint!(crate::ipc::Msg));
+///     assert_eq!(fingerprint!([Msg; 2]), fingerprint!([ipc::Msg; 2]));
+///     assert_ne!(fingerprint!(ipc::Msg), fingerprint!(old::Msg));
+///     assert_ne!(fingerprint!([ipc::Msg; 2]), fingerprint!([old::Msg; 2]));
+/// }
+/// ```
+pub const fn fingerprint<T>(name: &str, fields: &[Field]) -> u32 {
+    let mut hash = hash_bytes(FNV_OFFSET, name.as_bytes());
+    hash = hash_usize(hash, core::mem::size_of::<T>());
+    hash = hash_usize(hash, core::mem::align_of::<T>());
+    hash_fields(hash, fields)
+}
+
+const fn hash_fields(mut hash: u32, fields: &[Field]) -> u32 {
+    hash = hash_usize(hash, fields.len());
+
+    let mut i = 0;
+    while i < fields.len() {
+        let field = &fields[i];
+        hash = hash_usize(hash, field.name.len());
+        hash = hash_bytes(hash, field.name.as_bytes());
+        hash = hash_usize(hash, field.offset);
+        hash = hash_usize(hash, field.size);
+        hash = hash_usize(hash, field.align);
+        hash = hash_fields(hash, field.fields);
+        i += 1;
+    }
+
+    hash
+}
+
+// NOTE hashed as `u32` so the fingerprint doesn't depend on the pointer width
+const fn hash_usize(hash: u32, value: usize) -> u32 {
+    hash_bytes(hash, &(value as u32).to_le_bytes())
+}
+
+const fn hash_bytes(mut hash: u32, bytes: &[u8]) -> u32 {
+    let mut i = 0;
+    while i < bytes.len() {
+        hash ^= bytes[i] as u32;
+        hash = hash.wrapping_mul(FNV_PRIME);
+        i += 1;
+    }
+
+    hash
+}
+
+const fn write_bytes<const N: usize>(buffer: &mut [u8; N], mut pos: usize, bytes: &[u8]) -> usize {
+    let mut i = 0;
+    while i < bytes.len() {
+        buffer[pos] = bytes[i];
+        pos += 1;
+        i += 1;
+    }
+
+    pos
+}
+
+/// Implemented for the types that `#[shared(uninit)]` variables can have
+#[diagnostic::on_unimplemented(
+    message = "`#[shared(uninit)]` variables must have type `MaybeUninit<T>`"
+)]
+pub trait Uninit {}
+
+impl<T> Uninit for MaybeUninit<T> {}
+
+pub fn is_uninit<T>()
 where
-    T: DataNotCode + ?Sized,
+    T: Uninit,
 {
 }
+
+pub const fn is_zeroed(bytes: &[u8]) -> bool {
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] != 0 {
+            return false;
+        }
+        i += 1;
+    }
+
+    true
+}
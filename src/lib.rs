@@ -2,12 +2,28 @@
 #![deny(rust_2018_idioms)]
 #![doc = include_str!("../README.md")]
 #![deny(missing_docs)]
-#![feature(auto_traits)]
-#![feature(negative_impls)]
+#![cfg_attr(feature = "nightly", feature(auto_traits))]
+#![cfg_attr(feature = "nightly", feature(negative_impls))]
 #![no_std]
+#![recursion_limit = "256"]
 
-pub use microamp_macros::shared;
+pub use microamp_macros::{shared, shared_block, shared_ptr, shared_ref, DataNotCode, Shareable};
 
+pub use crate::{
+    abi::{abi_hash, AbiHash, AbiMismatch},
+    data_not_code::DataNotCode,
+    read_only::ReadOnly,
+    shareable::Shareable,
+    shared_cell::SharedCell,
+    shared_ptr::{SharedPtr, SharedRef},
+};
+
+mod abi;
 mod cfail;
+mod data_not_code;
 #[doc(hidden)]
 pub mod export;
+mod read_only;
+mod shareable;
+mod shared_cell;
+mod shared_ptr;
@@ -0,0 +1,90 @@
+use core::{
+    cell::UnsafeCell,
+    ptr,
+    sync::atomic::{self, Ordering},
+};
+
+use crate::SharedCell;
+
+/// A read-only view of a shared variable that's written by a different core
+///
+/// This is what the cores that are not the writer of a `#[shared(writer = N)]` variable get
+#[repr(transparent)]
+pub struct ReadOnly<T> {
+    // NOTE the writer core may modify the value at any time
+    inner: UnsafeCell<T>,
+}
+
+unsafe impl<T> Sync for ReadOnly<T> where T: Sync {}
+
+impl<T> ReadOnly<T> {
+    /// Returns a raw pointer to the variable
+    pub fn as_ptr(&self) -> *const T {
+        self.inner.get()
+    }
+}
+
+impl<T> ReadOnly<T>
+where
+    T: Copy,
+{
+    /// Reads the current value of the variable
+    ///
+    /// This performs a volatile load
+    ///
+    /// # Safety
+    ///
+    /// The writer core must not modify the variable while it's being read; the read would be a data
+    /// race
+    pub unsafe fn read(&self) -> T {
+        ptr::read_volatile(self.inner.get())
+    }
+}
+
+impl<T> ReadOnly<SharedCell<T>>
+where
+    T: Copy,
+{
+    /// Reads the contents of the cell
+    ///
+    /// This performs a volatile load
+    ///
+    /// # Safety
+    ///
+    /// The writer core must not modify the contents while they are being read; the read would be a
+    /// data race
+    pub unsafe fn read(&self) -> T {
+        (*self.inner.get()).read()
+    }
+}
+
+macro_rules! atomics {
+    ($($Atomic:ident => $T:ty,)+) => {
+        $(
+            impl ReadOnly<atomic::$Atomic> {
+                /// Loads the value of the atomic variable with the given memory `order`
+                pub fn load(&self, order: Ordering) -> $T {
+                    unsafe { (*self.inner.get()).load(order) }
+                }
+            }
+        )+
+    };
+}
+
+atomics! {
+    AtomicBool => bool,
+    AtomicI8 => i8,
+    AtomicI16 => i16,
+    AtomicI32 => i32,
+    AtomicIsize => isize,
+    AtomicU8 => u8,
+    AtomicU16 => u16,
+    AtomicU32 => u32,
+    AtomicUsize => usize,
+}
+
+#[cfg(target_has_atomic = "64")]
+atomics! {
+    AtomicI64 => i64,
+    AtomicU64 => u64,
+}
@@ -0,0 +1,145 @@
+use core::{
+    cell::UnsafeCell,
+    marker::PhantomData,
+    mem::{self, MaybeUninit},
+    sync::atomic,
+};
+
+use crate::{export::Field, AbiHash, ReadOnly, SharedCell, SharedPtr, SharedRef};
+
+/// Types whose layout doesn't depend on the compilation target
+///
+/// Cores compiled for different targets can only share variables of these types. Use
+/// `#[derive(Shareable)]` to implement this trait for a `#[repr(C)]` or `#[repr(transparent)]`
+/// struct, or for an enum with an explicit integer representation (e.g. `#[repr(u8)]`), whose
+/// fields are all `Shareable`.
+///
+/// # Safety
+///
+/// The size, alignment and field offsets of the type must be the same on all the targets
+#[diagnostic::on_unimplemented(
+    message = "`{Self}` may have a different layout on each target",
+    note = "the cores are compiled for different targets; `#[derive(Shareable)]` on a \
+            `#[repr(C)]` type whose fields are `Shareable` guarantees a stable layout"
+)]
+pub unsafe trait Shareable: Sized {
+    /// The size of the type in bytes
+    const SIZE: usize = mem::size_of::<Self>();
+
+    /// The alignment of the type in bytes
+    const ALIGN: usize = mem::align_of::<Self>();
+
+    #[doc(hidden)]
+    const FIELDS: &'static [Field] = &[];
+
+    /// The sizes, in bytes, of the atomics the type contains, as a bitmask
+    #[doc(hidden)]
+    const ATOMICS: u8 = 0;
+}
+
+macro_rules! primitives {
+    ($($T:ty,)+) => {
+        $(
+            unsafe impl Shareable for $T {}
+        )+
+    };
+}
+
+// NOTE `u128` and `i128` are not included because their alignment differs between targets.
+// `usize`, `isize` and the types built on top of them have the size of a pointer
+primitives! {
+    (),
+    bool,
+    char,
+    f32,
+    f64,
+    i8,
+    i16,
+    i32,
+    i64,
+    isize,
+    u8,
+    u16,
+    u32,
+    u64,
+    usize,
+    AbiHash,
+}
+
+macro_rules! atomics {
+    ($($T:ty,)+) => {
+        $(
+            unsafe impl Shareable for $T {
+                // NOTE the sizes of the atomic types are powers of two
+                const ATOMICS: u8 = mem::size_of::<Self>() as u8;
+            }
+        )+
+    };
+}
+
+atomics! {
+    atomic::AtomicBool,
+    atomic::AtomicI8,
+    atomic::AtomicI16,
+    atomic::AtomicI32,
+    atomic::AtomicIsize,
+    atomic::AtomicU8,
+    atomic::AtomicU16,
+    atomic::AtomicU32,
+    atomic::AtomicUsize,
+}
+
+#[cfg(target_has_atomic = "64")]
+atomics! {
+    atomic::AtomicI64,
+    atomic::AtomicU64,
+}
+
+// the layout of the first element stands for the layout of all of them
+unsafe impl<T, const N: usize> Shareable for [T; N]
+where
+    T: Shareable,
+{
+    const FIELDS: &'static [Field] = if N == 0 {
+        &[]
+    } else {
+        &[Field {
+            name: "[0]",
+            offset: 0,
+            size: T::SIZE,
+            align: T::ALIGN,
+            fields: T::FIELDS,
+        }]
+    };
+
+    const ATOMICS: u8 = T::ATOMICS;
+}
+
+unsafe impl<T> Shareable for PhantomData<T> {}
+
+// the wrappers have the layout of the wrapped type
+macro_rules! wrappers {
+    ($($Wrapper:ident,)+) => {
+        $(
+            unsafe impl<T> Shareable for $Wrapper<T>
+            where
+                T: Shareable,
+            {
+                const FIELDS: &'static [Field] = T::FIELDS;
+
+                const ATOMICS: u8 = T::ATOMICS;
+            }
+        )+
+    };
+}
+
+wrappers! {
+    MaybeUninit,
+    UnsafeCell,
+    SharedCell,
+    ReadOnly,
+}
+
+unsafe impl<T> Shareable for SharedPtr<T> {}
+
+unsafe impl<T> Shareable for SharedRef<T> {}
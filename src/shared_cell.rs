@@ -0,0 +1,71 @@
+use core::{cell::UnsafeCell, ptr};
+
+/// A mutable memory location that can be placed in a `#[shared]` (non-`mut`) static variable
+///
+/// Unlike a `static mut` variable, a `#[shared] static X: SharedCell<T>` variable can be named
+/// without `unsafe` and never hands out references to a mutable static. The accesses to the
+/// contents of the cell are explicit: volatile reads and writes, or a mutable reference whose
+/// exclusivity the caller must guarantee.
+#[repr(transparent)]
+pub struct SharedCell<T> {
+    inner: UnsafeCell<T>,
+}
+
+// NOTE(Send) like `Mutex`: the cores that access the cell may own `T` at different times
+unsafe impl<T> Sync for SharedCell<T> where T: Send {}
+
+impl<T> SharedCell<T> {
+    /// Creates a new cell that contains the given `value`
+    pub const fn new(value: T) -> Self {
+        SharedCell {
+            inner: UnsafeCell::new(value),
+        }
+    }
+
+    /// Returns a raw pointer to the contents of the cell
+    pub const fn as_ptr(&self) -> *mut T {
+        self.inner.get()
+    }
+
+    /// Returns a mutable reference to the contents of the cell
+    ///
+    /// # Safety
+    ///
+    /// No other reference to the contents, on this or on any other core, may exist while the
+    /// returned reference is in use. Usually this is ensured by holding a lock built on top of an
+    /// atomic shared variable.
+    #[allow(clippy::mut_from_ref)]
+    pub unsafe fn get_mut(&self) -> &mut T {
+        &mut *self.inner.get()
+    }
+
+    /// Reads the contents of the cell
+    ///
+    /// This performs a volatile load
+    ///
+    /// # Safety
+    ///
+    /// No other core may modify the contents while they are being read; the read would be a data
+    /// race
+    pub unsafe fn read(&self) -> T
+    where
+        T: Copy,
+    {
+        ptr::read_volatile(self.inner.get())
+    }
+
+    /// Overwrites the contents of the cell with `value`
+    ///
+    /// This performs a volatile store
+    ///
+    /// # Safety
+    ///
+    /// No other core may access the contents while they are being written; the write would be a
+    /// data race
+    pub unsafe fn write(&self, value: T)
+    where
+        T: Copy,
+    {
+        ptr::write_volatile(self.inner.get(), value)
+    }
+}
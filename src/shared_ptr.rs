@@ -0,0 +1,206 @@
+use core::{fmt, marker::PhantomData, ops::Deref, ptr};
+
+use crate::DataNotCode;
+
+extern "C" {
+    // start of the `.shared` output section; provided by the linker script of each core
+    static __sshared: u8;
+}
+
+/// The address of the shared region in the image of this core
+fn base() -> usize {
+    ptr::addr_of!(__sshared) as usize
+}
+
+/// The offset of a null `SharedPtr`
+const NULL: usize = usize::MAX;
+
+/// A pointer to shared data that means the same thing on every core
+///
+/// The pointer is stored as an offset from the start of the `.shared` section (the `__sshared`
+/// symbol) so it can itself be placed in shared memory, and it stays valid on cores that map the
+/// shared memory at a different address. Use [`shared_ptr!`] to get a pointer to a `#[shared]`
+/// variable.
+///
+/// [`shared_ptr!`]: macro.shared_ptr.html
+#[repr(transparent)]
+pub struct SharedPtr<T> {
+    offset: usize,
+    _marker: PhantomData<*const T>,
+}
+
+// NOTE(Send, Sync) like `usize`: dereferencing the pointer is `unsafe`
+unsafe impl<T> Send for SharedPtr<T> {}
+unsafe impl<T> Sync for SharedPtr<T> {}
+
+// the pointee is a `#[shared]` variable, or part of one, so the pointer doesn't point into the
+// private memory of a core
+unsafe impl<T> DataNotCode for SharedPtr<T> where T: DataNotCode {}
+
+impl<T> SharedPtr<T> {
+    /// Creates a null pointer
+    pub const fn null() -> Self {
+        SharedPtr {
+            offset: NULL,
+            _marker: PhantomData,
+        }
+    }
+
+    #[doc(hidden)]
+    pub unsafe fn from_ptr(ptr: *const T) -> Self {
+        SharedPtr {
+            offset: (ptr as usize).wrapping_sub(base()),
+            _marker: PhantomData,
+        }
+    }
+
+    /// Returns `true` if the pointer is null
+    pub fn is_null(self) -> bool {
+        self.offset == NULL
+    }
+
+    /// Returns the address of the pointee in the memory map of this core
+    pub fn as_ptr(self) -> *mut T {
+        if self.is_null() {
+            ptr::null_mut()
+        } else {
+            base().wrapping_add(self.offset) as *mut T
+        }
+    }
+
+    /// Returns a reference to the pointee, or `None` if the pointer is null
+    ///
+    /// # Safety
+    ///
+    /// No core may modify the pointee while the returned reference is in use, unless it's done
+    /// through interior mutability (e.g. atomics or `SharedCell`)
+    pub unsafe fn as_ref<'a>(self) -> Option<&'a T> {
+        self.as_ptr().as_ref()
+    }
+}
+
+impl<T, const N: usize> SharedPtr<[T; N]> {
+    /// Returns a pointer to the element at `index`; a null pointer stays null
+    ///
+    /// # Panics
+    ///
+    /// If `index` is out of bounds
+    pub fn element(self, index: usize) -> SharedPtr<T> {
+        assert!(index < N, "index out of bounds");
+
+        if self.is_null() {
+            SharedPtr::null()
+        } else {
+            SharedPtr {
+                offset: self.offset.wrapping_add(index * core::mem::size_of::<T>()),
+                _marker: PhantomData,
+            }
+        }
+    }
+}
+
+impl<T> Clone for SharedPtr<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T> Copy for SharedPtr<T> {}
+
+impl<T> PartialEq for SharedPtr<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.offset == other.offset
+    }
+}
+
+impl<T> Eq for SharedPtr<T> {}
+
+impl<T> fmt::Debug for SharedPtr<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.is_null() {
+            f.write_str("SharedPtr(null)")
+        } else {
+            write!(f, "SharedPtr(__sshared + {:#x})", self.offset)
+        }
+    }
+}
+
+impl<T> From<SharedRef<T>> for SharedPtr<T> {
+    fn from(r: SharedRef<T>) -> Self {
+        r.ptr
+    }
+}
+
+/// A reference to a `#[shared]` variable that means the same thing on every core
+///
+/// Like [`SharedPtr`] but never null, and it can be dereferenced without `unsafe`. Use
+/// [`shared_ref!`] to get a reference to a (non-`mut`) `#[shared]` variable.
+///
+/// [`shared_ref!`]: macro.shared_ref.html
+#[repr(transparent)]
+pub struct SharedRef<T> {
+    ptr: SharedPtr<T>,
+}
+
+// NOTE(Send, Sync) like `&'static T`
+unsafe impl<T> Send for SharedRef<T> where T: Sync {}
+unsafe impl<T> Sync for SharedRef<T> where T: Sync {}
+
+unsafe impl<T> DataNotCode for SharedRef<T> where T: DataNotCode {}
+
+impl<T> SharedRef<T> {
+    #[doc(hidden)]
+    pub unsafe fn from_ptr(ptr: *const T) -> Self {
+        SharedRef {
+            ptr: SharedPtr::from_ptr(ptr),
+        }
+    }
+
+    /// Returns the address of the referent in the memory map of this core
+    pub fn as_ptr(self) -> *const T {
+        self.ptr.as_ptr()
+    }
+}
+
+impl<T, const N: usize> SharedRef<[T; N]> {
+    /// Returns a reference to the element at `index`
+    ///
+    /// # Panics
+    ///
+    /// If `index` is out of bounds
+    pub fn element(self, index: usize) -> SharedRef<T> {
+        SharedRef {
+            ptr: self.ptr.element(index),
+        }
+    }
+}
+
+impl<T> Deref for SharedRef<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.ptr.as_ptr() }
+    }
+}
+
+impl<T> Clone for SharedRef<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T> Copy for SharedRef<T> {}
+
+impl<T> PartialEq for SharedRef<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.ptr == other.ptr
+    }
+}
+
+impl<T> Eq for SharedRef<T> {}
+
+impl<T> fmt::Debug for SharedRef<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "SharedRef(__sshared + {:#x})", self.ptr.offset)
+    }
+}
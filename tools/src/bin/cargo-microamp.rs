@@ -4,22 +4,12 @@
 #![deny(rust_2018_idioms)]
 #![deny(warnings)]
 
-use core::{fmt, iter::FromIterator};
-use std::{
-    collections::BTreeMap,
-    env, fs,
-    process::{self, Command},
-    time::SystemTime,
-};
+use std::{env, path::Path, process};
 
-use cargo_project::{Artifact, Profile, Project};
-use clap::{App, Arg};
+use clap::{App, Arg, SubCommand};
 use exitfailure::ExitFailure;
 use failure::{bail, ensure, format_err};
-use filetime::FileTime;
-use tempdir::TempDir;
-use walkdir::WalkDir;
-use xmas_elf::{sections::SectionData, symbol_table::Entry, ElfFile};
+use microamp_build::{Artifact, Baseline, CommandFailed, Config, Core, Features, Pipeline};
 
 fn main() -> Result<(), ExitFailure> {
     process::exit(run()?)
@@ -39,6 +29,7 @@ fn run() -> Result<i32, failure::Error> {
                 .short("c")
                 .takes_value(true)
                 .value_name("N")
+                .global(true)
                 .help("Number of cores to build this program for (default: 2)"),
         )
         .arg(
@@ -46,12 +37,26 @@ fn run() -> Result<i32, failure::Error> {
                 .long("target")
                 .takes_value(true)
                 .value_name("TRIPLE")
+                .global(true)
                 .help("Comma separated list of target triples for which the code is compiled"),
         )
+        .arg(
+            Arg::with_name("rustflags")
+                .long("rustflags")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .value_name("CORE=FLAGS")
+                .global(true)
+                .help(
+                    "Space separated list of extra flags passed to rustc when compiling for CORE",
+                ),
+        )
         .arg(
             Arg::with_name("verbose")
                 .long("verbose")
                 .short("v")
+                .global(true)
                 .help("Use verbose output"),
         )
         .arg(
@@ -59,6 +64,7 @@ fn run() -> Result<i32, failure::Error> {
                 .long("example")
                 .takes_value(true)
                 .value_name("NAME")
+                .global(true)
                 .help("Build only the specified example"),
         )
         .arg(
@@ -66,6 +72,7 @@ fn run() -> Result<i32, failure::Error> {
                 .long("bin")
                 .takes_value(true)
                 .value_name("BIN")
+                .global(true)
                 .help("Build only the specified binary"),
         )
         .arg(
@@ -73,26 +80,54 @@ fn run() -> Result<i32, failure::Error> {
                 .long("features")
                 .takes_value(true)
                 .value_name("FEATURES")
+                .global(true)
                 .help("Space-separated list of features to activate"),
         )
         .arg(
             Arg::with_name("all-features")
                 .long("all-features")
                 .takes_value(false)
+                .global(true)
                 .help("Activate all available features"),
         )
         .arg(
             Arg::with_name("release")
                 .long("release")
+                .global(true)
                 .help("Build artifacts in release mode, with optimizations"),
         )
         .arg(
             Arg::with_name("check")
                 .long("check")
+                .global(true)
                 .help("Do not link; only compile check"),
         )
+        .subcommand(
+            SubCommand::with_name("abi-check")
+                .about(
+                    "Build and compare the layout of the shared sections against a baseline file",
+                )
+                .arg(
+                    Arg::with_name("baseline")
+                        .long("baseline")
+                        .takes_value(true)
+                        .value_name("PATH")
+                        .default_value("microamp-abi.txt")
+                        .help("The baseline file"),
+                )
+                .arg(
+                    Arg::with_name("update")
+                        .long("update")
+                        .help("Write the layout of this build to the baseline file"),
+                ),
+        )
         .get_matches();
 
+    // NOTE the options are global so they can also be given after the subcommand, in which case
+    // only the subcommand sees them
+    let abi_check = matches.subcommand_matches("abi-check");
+    let matches = abi_check.unwrap_or(&matches);
+
     let targets = if let Some(target) = matches.value_of("target") {
         target
             .split(',')
@@ -114,401 +149,147 @@ fn run() -> Result<i32, failure::Error> {
         2
     };
     let check = matches.is_present("check");
-    let build_profile = if matches.is_present("release") {
-        if check {
-            bail!("can't specify both `--check` and `--release`");
-        }
+    let release = matches.is_present("release");
+    if release && check {
+        bail!("can't specify both `--check` and `--release`");
+    }
+    if check && abi_check.is_some() {
+        bail!("`abi-check` needs the linked images; it can't be combined with `--check`");
+    }
 
-        Profile::Release
-    } else {
-        Profile::Dev
-    };
-    let verbose = matches.is_present("verbose");
+    let mut cores = (0..cores)
+        .map(|i| Core {
+            target: targets.get(i).and_then(|t| t.map(String::from)),
+            rustflags: vec![],
+        })
+        .collect::<Vec<_>>();
+
+    for value in matches.values_of("rustflags").into_iter().flatten() {
+        let (core, flags) = match value.find('=') {
+            Some(pos) => (&value[..pos], &value[pos + 1..]),
+            None => bail!("`--rustflags` expects a value of the form CORE=FLAGS"),
+        };
+        let core = core
+            .trim()
+            .parse::<usize>()
+            .map_err(|_| format_err!("`{}` is not a valid core index", core))?;
+        ensure!(
+            core < cores.len(),
+            "`--rustflags` was given flags for core {} but only {} cores are being built",
+            core,
+            cores.len()
+        );
+
+        cores[core]
+            .rustflags
+            .extend(flags.split_whitespace().map(String::from));
+    }
 
     let artifact = match (matches.value_of("bin"), matches.value_of("example")) {
-        (Some(bin), None) => Artifact::Bin(bin),
-        (None, Some(ex)) => Artifact::Example(ex),
+        (Some(bin), None) => Artifact::Bin(bin.to_owned()),
+        (None, Some(ex)) => Artifact::Example(ex.to_owned()),
         _ => bail!("please specify --example <NAME> or --bin <NAME>"),
     };
 
-    let meta = rustc_version::version_meta()?;
-    let host = meta.host;
-    let project = Project::query(env::current_dir()?)?;
-
-    // "touch" some source file to trigger a rebuild
-    let root = project.toml().parent().expect("UNREACHABLE");
-    let now = FileTime::from_system_time(SystemTime::now());
-    match artifact {
-        Artifact::Bin(bin) => {
-            if bin == project.name() {
-                filetime::set_file_times(root.join("src/main.rs"), now, now)?
-            } else {
-                filetime::set_file_times(root.join(format!("src/bin/{}.rs", bin)), now, now)?
-            }
-        }
-
-        Artifact::Example(ex) => {
-            filetime::set_file_times(root.join(format!("examples/{}.rs", ex)), now, now)?
-        }
-
-        Artifact::Lib => filetime::set_file_times(root.join("src/lib.rs"), now, now)?,
-
-        _ => {
-            // look for some rust source file and "touch" it
-            let src = root.join("src");
-            let haystack = if src.exists() { &src } else { root };
-
-            for entry in WalkDir::new(haystack) {
-                let entry = entry?;
-                let path = entry.path();
-
-                if path.extension().map(|ext| ext == "rs").unwrap_or(false) {
-                    filetime::set_file_times(path, now, now)?;
-                    break;
-                }
-            }
-        }
-    }
-
-    let cargo = || {
-        let mut c = Command::new("cargo");
-        c.arg("rustc");
-        match artifact {
-            Artifact::Bin(bin) => {
-                c.args(&["--bin", bin]);
-            }
-            Artifact::Example(ex) => {
-                c.args(&["--example", ex]);
-            }
-            _ => {}
-        }
-        if build_profile.is_release() {
-            c.arg("--release");
-        }
-        if matches.is_present("all-features") {
-            c.arg("--all-features");
-        } else if let Some(features) = matches.value_of("features") {
-            c.args(&["--features", features]);
-        }
-        c
+    let features = if matches.is_present("all-features") {
+        Features::All
+    } else if let Some(features) = matches.value_of("features") {
+        Features::List(features.to_owned())
+    } else {
+        Features::Default
     };
 
-    if check {
-        if targets.is_empty() {
-            // data
-            let mut c = cargo();
-            c.args(&[
-                "--",
-                "--cfg",
-                "microamp",
-                "-C",
-                "linker=microamp-true",
-                "-A",
-                "warnings",
-            ]);
-            if verbose {
-                eprintln!("{:?}", c);
-            }
-            let status = c.status()?;
-            if !status.success() {
-                return Ok(status.code().unwrap_or(1));
-            }
-        }
-
-        for i in 0..cores {
-            // data
-            if !targets.is_empty() {
-                let mut c = cargo();
-                if let Some(target) = targets.get(i).unwrap_or(&None) {
-                    c.args(&["--target", target]);
-                }
-                c.args(&[
-                    "--",
-                    "--cfg",
-                    "microamp",
-                    "-C",
-                    "linker=microamp-true",
-                    "-A",
-                    "warnings",
-                ]);
-                if verbose {
-                    eprintln!("{:?}", c);
-                }
-                let status = c.status()?;
-                if !status.success() {
-                    return Ok(status.code().unwrap_or(1));
-                }
-            }
-
-            // code
-            let mut c = cargo();
-            if let Some(target) = targets.get(i).unwrap_or(&None) {
-                c.args(&["--target", target]);
-            }
-            c.args(&["--", "--cfg"]);
-            c.arg(&format!("core=\"{}\"", i));
-            c.args(&["-C", "linker=microamp-true"]);
-            if verbose {
-                eprintln!("{:?}", c);
-            }
-            let status = c.status()?;
-            if !status.success() {
-                return Ok(status.code().unwrap_or(1));
-            }
-        }
+    let pipeline = Pipeline::new(
+        Config {
+            artifact,
+            cores,
+            release,
+            features,
+            verbose: matches.is_present("verbose"),
+        },
+        env::current_dir()?,
+    )?;
+
+    let res = if check {
+        pipeline.check()
     } else {
-        // address -> (size, name)
-        let mut base: Option<(String, Symbols)> = None;
-        for i in 0..cores {
-            let mut c = cargo();
-            if let Some(target) = targets.get(i).unwrap_or(&None) {
-                c.args(&["--target", target]);
-            }
-            c.args(&[
-                "--",
-                "-C",
-                "lto",
-                "--cfg",
-                "microamp",
-                "--emit=obj",
-                "-A",
-                "warnings",
-                "-C",
-                "linker=microamp-true",
-            ]);
-            if verbose {
-                eprintln!("{:?}", c);
-            }
-            let status = c.status()?;
-            if !status.success() {
-                return Ok(status.code().unwrap_or(1));
+        pipeline.build().and_then(|build| {
+            for image in build.images.iter().filter(|image| image.fresh) {
+                eprintln!("{:>12} {}", "Fresh", image.path.display());
             }
 
-            let path = project.path(
-                artifact,
-                build_profile,
-                targets.get(i).and_then(|t| t.as_ref().map(|s| *s)),
-                &host,
-            )?;
-            let parent = path.parent().expect("unreachable");
-            let (haystack, name) = match artifact {
-                Artifact::Bin(bin) => (parent.join("deps"), bin),
-                Artifact::Example(ex) => (parent.to_owned(), ex),
-                _ => unreachable!(),
-            };
-
-            let prefix = format!("{}-", name.replace('-', "_"));
-            let mut so = None;
-            // most recently modified
-            let mut mrm = SystemTime::UNIX_EPOCH;
-            for e in fs::read_dir(haystack)? {
-                let e = e?;
-                let p = e.path();
-
-                if p.extension().map(|ext| ext == "o").unwrap_or(false)
-                    && p.file_stem()
-                        .expect("unreachable")
-                        .to_str()
-                        .expect("unreachable")
-                        .starts_with(&prefix)
-                {
-                    let modified = e.metadata()?.modified()?;
-                    if so.is_none() {
-                        so = Some(p);
-                        mrm = modified;
-                    } else {
-                        if modified > mrm {
-                            so = Some(p);
-                            mrm = modified;
-                        }
-                    }
-                }
-            }
-
-            // strip '.text' sections from the shared object file
-            let so = so.expect("UNREACHABLE");
-            let td = TempDir::new("cargo-microamp")?;
-            let obj = td.path().join("microamp-data.o");
-            fs::copy(&so, &obj)?;
-
-            // FIXME use a Rust library instead of shelling out to `strip`
-            let mut c = Command::new("arm-none-eabi-strip");
-            c.args(&["-R", "*", "-R", "!.shared", "--strip-unneeded"])
-                .arg(&obj);
-            if verbose {
-                eprintln!("{:?}", c);
+            if let Some(abi_check) = abi_check {
+                let path = Path::new(abi_check.value_of("baseline").expect("UNREACHABLE"));
+                compare_baseline(
+                    &Baseline::new(&build.layout),
+                    path,
+                    abi_check.is_present("update"),
+                )?;
             }
 
-            let status = c.status()?;
-            if !status.success() {
-                return Ok(status.code().unwrap_or(1));
-            }
-
-            let mut c = cargo();
-            if let Some(target) = targets.get(i).unwrap_or(&None) {
-                c.args(&["--target", target]);
-            }
-            c.args(&[
-                "--",
-                "--cfg",
-                &format!("core=\"{}\"", i),
-                "-C",
-                &format!("link-arg=-Tcore{}.x", i),
-                "-C",
-                &format!("link-arg={}", obj.display()),
-            ]);
-            if verbose {
-                eprintln!("{:?}", c);
-            }
-            let status = c.status()?;
-
-            if !status.success() {
-                return Ok(status.code().unwrap_or(1));
-            }
-
-            let filename = format!(
-                "{}-{}",
-                path.file_name()
-                    .expect("unreachable")
-                    .to_str()
-                    .expect("unreachable"),
-                i
-            );
-            let dst = parent.join(&filename);
-
-            fs::rename(&path, &dst)?;
-
-            let contents = fs::read(&dst)?;
-            let elf = ElfFile::new(&contents).map_err(failure::err_msg)?;
-
-            let mut shndx = None;
-            for i in 1..elf.header.pt2.sh_count() {
-                if let Ok(sh) = elf.section_header(i) {
-                    if sh.get_name(&elf) == Ok(".shared") {
-                        shndx = Some(i);
-                        break;
-                    }
-                }
-            }
-
-            let shndx =
-                shndx.ok_or_else(|| format_err!("({}) `.shared` section is missing", filename))?;
-
-            if let Some(symtab) = elf.find_section_by_name(".symtab") {
-                match symtab.get_data(&elf).map_err(failure::err_msg)? {
-                    SectionData::SymbolTable32(entries) => {
-                        if let Some((base_filename, base_symbols)) = &base {
-                            let symbols = entries
-                                .iter()
-                                .filter_map(|entry| {
-                                    let size = entry.size();
-                                    if entry.shndx() == shndx && size != 0 {
-                                        Some((
-                                            entry.value(),
-                                            (size, entry.get_name(&elf).ok().map(String::from)),
-                                        ))
-                                    } else {
-                                        None
-                                    }
-                                })
-                                .collect::<Symbols>();
-
-                            ensure!(
-                                &symbols == base_symbols,
-                                "the layout of the `.shared` section doesn't match\n\
-                                 {}:\n{:#?}\n{}\n{:#?}",
-                                base_filename,
-                                base_symbols,
-                                filename,
-                                symbols,
-                            );
-                        } else {
-                            base = Some((
-                                filename,
-                                entries
-                                    .iter()
-                                    .filter_map(|entry| {
-                                        let size = entry.size();
-                                        if entry.shndx() == shndx && size != 0 {
-                                            Some((
-                                                entry.value(),
-                                                (size, entry.get_name(&elf).ok().map(String::from)),
-                                            ))
-                                        } else {
-                                            None
-                                        }
-                                    })
-                                    .collect(),
-                            ))
-                        }
-                    }
-
-                    SectionData::SymbolTable64(_) => {
-                        bail!("64-bit ELF files are not currently supported")
-                    }
+            Ok(())
+        })
+    };
 
-                    _ => bail!("malformed .symtab section"),
-                }
-            }
-        }
+    match res {
+        Ok(()) => Ok(0),
+        // the failed command has already reported the error
+        Err(e) => match e.downcast::<CommandFailed>() {
+            Ok(cf) => Ok(cf.code.unwrap_or(1)),
+            Err(e) => Err(e),
+        },
     }
-
-    Ok(0)
-}
-
-#[derive(PartialEq)]
-struct Symbols {
-    entries: BTreeMap<u64, Symbol>,
 }
 
-impl FromIterator<(u64, (u64, Option<String>))> for Symbols {
-    fn from_iter<I>(iter: I) -> Self
-    where
-        I: IntoIterator<Item = (u64, (u64, Option<String>))>,
-    {
-        Symbols {
-            entries: iter
-                .into_iter()
-                .map(|(address, (size, name))| (address, Symbol { size, name }))
-                .collect(),
-        }
+/// Compares the layout of this build against the baseline at `path`, or makes it the new baseline
+fn compare_baseline(current: &Baseline, path: &Path, update: bool) -> Result<(), failure::Error> {
+    if update {
+        current.store(path)?;
+        eprintln!("{:>12} {}", "Updated", path.display());
+        return Ok(());
     }
-}
-
-impl fmt::Debug for Symbols {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        struct Hex(u64);
-
-        impl fmt::Debug for Hex {
-            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-                write!(f, "{:#08x}", self.0)
-            }
-        }
-
-        let mut s = f.debug_map();
-        for (address, symbol) in &self.entries {
-            s.entry(&Hex(*address), symbol);
-        }
 
-        s.finish()
+    ensure!(
+        path.exists(),
+        "the baseline `{}` doesn't exist; create it with `cargo microamp abi-check --update`",
+        path.display()
+    );
+
+    let changes = Baseline::load(path)?.compare(current);
+    for change in &changes {
+        let kind = if change.is_breaking() {
+            "Breaking"
+        } else if change.grows_section() {
+            "Compatible (section grew)"
+        } else {
+            "Compatible"
+        };
+        eprintln!("{:>12} {}", kind, change);
     }
-}
 
-#[derive(PartialEq)]
-struct Symbol {
-    size: u64,
-    name: Option<String>,
-}
+    let breaking = changes.iter().filter(|change| change.is_breaking()).count();
+    ensure!(
+        breaking == 0,
+        "{} breaking change(s) to the layout of the shared sections since `{}`",
+        breaking,
+        path.display()
+    );
+
+    if changes.iter().any(|change| change.grows_section()) {
+        eprintln!(
+            "{:>12} some shared sections grew; the memory map of the deployed firmware must have \
+             room for them",
+            "Note"
+        );
+    }
 
-impl fmt::Debug for Symbol {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        let mut s = f.debug_struct("Symbol");
-        s.field("size", &self.size);
-        if let Some(name) = &self.name {
-            s.field("name", name);
-        } else {
-            s.field("name", &"?");
-        }
-        s.finish()
+    if !changes.is_empty() {
+        eprintln!(
+            "{:>12} the changes are compatible; run `cargo microamp abi-check --update` to \
+             record them",
+            "Note"
+        );
     }
+
+    Ok(())
 }